@@ -0,0 +1,440 @@
+use std::collections::HashSet;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::Chars;
+
+use serde_json as sj;
+
+/// A line and column in the source of a rules file, both starting at 1,
+/// where the column is counted in bytes, the same as serde_json does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+// ----------------------------------------------------------------------------
+// Node
+// ----------------------------------------------------------------------------
+
+/// A value of a rules file along with where it is located, which serde_json
+/// doesn't keep track of. Nodes of rules files that were included by another
+/// one also keep track of which file they came from.
+#[derive(Debug, Clone)]
+pub struct Node {
+    /// The position of the value in the source, which is only known for JSON.
+    pub position: Option<Position>,
+    /// The path of the included rules file that the value came from, which
+    /// is None for the rules file that fassoc-proxy was given.
+    pub file: Option<Rc<str>>,
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum NodeKind {
+    Object(Vec<Entry>),
+    Array(Vec<Node>),
+    String(String),
+    /// Numbers, booleans and null.
+    Other(sj::Value),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    /// The position of the key, rather than the value.
+    pub position: Option<Position>,
+    pub value: Node,
+}
+
+impl Node {
+    /// Parses JSON without comments, which is expected to be valid, as it has
+    /// already been parsed by serde_json.
+    pub fn parse(source: &str, file: Option<Rc<str>>) -> Option<Node> {
+        let mut parser = Parser {
+            chars: source.chars().peekable(),
+            position: Position { line: 1, column: 1 },
+            file,
+        };
+
+        parser.parse_value()
+    }
+
+    /// Converts a value of a rules file written in a format other than JSON,
+    /// for which no positions are known.
+    pub fn from_value(value: &sj::Value, file: Option<Rc<str>>) -> Node {
+        let kind = match value {
+            sj::Value::Object(object) => NodeKind::Object(
+                object
+                    .iter()
+                    .map(|(key, value)| Entry {
+                        key: key.to_owned(),
+                        position: None,
+                        value: Node::from_value(value, file.clone()),
+                    })
+                    .collect(),
+            ),
+            sj::Value::Array(array) => NodeKind::Array(
+                array
+                    .iter()
+                    .map(|item| Node::from_value(item, file.clone()))
+                    .collect(),
+            ),
+            sj::Value::String(string) => NodeKind::String(string.to_owned()),
+            other => NodeKind::Other(other.to_owned()),
+        };
+
+        Node {
+            position: None,
+            file,
+            kind,
+        }
+    }
+
+    pub fn to_value(&self) -> sj::Value {
+        match &self.kind {
+            NodeKind::Object(entries) => sj::Value::Object(
+                entries
+                    .iter()
+                    .map(|entry| (entry.key.to_owned(), entry.value.to_value()))
+                    .collect(),
+            ),
+            NodeKind::Array(items) => sj::Value::Array(items.iter().map(Node::to_value).collect()),
+            NodeKind::String(string) => sj::Value::String(string.to_owned()),
+            NodeKind::Other(value) => value.to_owned(),
+        }
+    }
+
+    /// An empty object, which isn't located anywhere.
+    pub fn object() -> Node {
+        Node {
+            position: None,
+            file: None,
+            kind: NodeKind::Object(Vec::new()),
+        }
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        match &self.kind {
+            NodeKind::Object(entries) => entries,
+            _ => &[],
+        }
+    }
+
+    pub fn items(&self) -> &[Node] {
+        match &self.kind {
+            NodeKind::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    pub fn field(&self, key: &str) -> Option<&Node> {
+        self.entries()
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| &entry.value)
+    }
+
+    pub fn keys(&self) -> HashSet<&str> {
+        self.entries()
+            .iter()
+            .map(|entry| entry.key.as_str())
+            .collect()
+    }
+
+    pub fn field_mut(&mut self, key: &str) -> Option<&mut Node> {
+        match &mut self.kind {
+            NodeKind::Object(entries) => entries
+                .iter_mut()
+                .find(|entry| entry.key == key)
+                .map(|entry| &mut entry.value),
+            _ => None,
+        }
+    }
+
+    /// Replaces the entry with the same key, keeping its place, or adds the
+    /// entry at the end if there is none. Does nothing if this isn't an object.
+    pub fn set(&mut self, entry: Entry) {
+        if let NodeKind::Object(entries) = &mut self.kind {
+            match entries
+                .iter_mut()
+                .find(|existing| existing.key == entry.key)
+            {
+                Some(existing) => *existing = entry,
+                None => entries.push(entry),
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Node> {
+        match &mut self.kind {
+            NodeKind::Object(entries) => {
+                let index = entries.iter().position(|entry| entry.key == key)?;
+                Some(entries.remove(index).value)
+            }
+            _ => None,
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Parser
+// ----------------------------------------------------------------------------
+
+/// A JSON parser that records the position of every value and key. It gives
+/// up on anything that isn't valid JSON, rather than reporting why, as the
+/// source has already been parsed by serde_json by the time it is validated.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: Position,
+    file: Option<Rc<str>>,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += c.len_utf8();
+        }
+
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    fn parse_value(&mut self) -> Option<Node> {
+        self.skip_whitespace();
+        let position = Some(self.position);
+
+        let kind = match self.chars.peek()? {
+            '{' => NodeKind::Object(self.parse_object()?),
+            '[' => NodeKind::Array(self.parse_array()?),
+            '"' => NodeKind::String(self.parse_string()?),
+            _ => {
+                let mut literal = String::new();
+
+                while let Some(c) = self
+                    .chars
+                    .peek()
+                    .copied()
+                    .filter(|c| !c.is_whitespace() && !",]}".contains(*c))
+                {
+                    self.next();
+                    literal.push(c);
+                }
+
+                NodeKind::Other(sj::from_str(&literal).ok()?)
+            }
+        };
+
+        Some(Node {
+            position,
+            file: self.file.clone(),
+            kind,
+        })
+    }
+
+    fn parse_object(&mut self) -> Option<Vec<Entry>> {
+        let mut entries: Vec<Entry> = Vec::new();
+        self.next();
+
+        loop {
+            self.skip_whitespace();
+
+            if entries.is_empty() && self.chars.peek() == Some(&'}') {
+                self.next();
+                return Some(entries);
+            }
+
+            let position = Some(self.position);
+            let key = self.parse_string()?;
+
+            self.skip_whitespace();
+
+            if self.next()? != ':' {
+                return None;
+            }
+
+            let value = self.parse_value()?;
+            entries.push(Entry {
+                key,
+                position,
+                value,
+            });
+
+            self.skip_whitespace();
+
+            match self.next()? {
+                ',' => continue,
+                '}' => return Some(entries),
+                _ => return None,
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Option<Vec<Node>> {
+        let mut items: Vec<Node> = Vec::new();
+        self.next();
+
+        loop {
+            self.skip_whitespace();
+
+            if items.is_empty() && self.chars.peek() == Some(&']') {
+                self.next();
+                return Some(items);
+            }
+
+            items.push(self.parse_value()?);
+
+            self.skip_whitespace();
+
+            match self.next()? {
+                ',' => continue,
+                ']' => return Some(items),
+                _ => return None,
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Option<String> {
+        if self.next()? != '"' {
+            return None;
+        }
+
+        let mut string = String::new();
+
+        loop {
+            match self.next()? {
+                '"' => return Some(string),
+                '\\' => match self.next()? {
+                    'b' => string.push('\u{8}'),
+                    'f' => string.push('\u{c}'),
+                    'n' => string.push('\n'),
+                    'r' => string.push('\r'),
+                    't' => string.push('\t'),
+                    'u' => {
+                        let mut code = self.parse_code_unit()?;
+
+                        // A character outside of the basic multilingual plane
+                        // is escaped as a pair of surrogates.
+                        if (0xD800..0xDC00).contains(&code) {
+                            if self.next()? != '\\' || self.next()? != 'u' {
+                                return None;
+                            }
+
+                            let low = self.parse_code_unit()?;
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low.checked_sub(0xDC00)?);
+                        }
+
+                        string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    c => string.push(c),
+                },
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn parse_code_unit(&mut self) -> Option<u32> {
+        let mut code: u32 = 0;
+
+        for _ in 0..4 {
+            code = code * 16 + self.next()?.to_digit(16)?;
+        }
+
+        Some(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, column: usize) -> Option<Position> {
+        Some(Position { line, column })
+    }
+
+    #[test]
+    fn positions_of_keys_and_values() {
+        let source = "{\n  \"a\": 1,\n  \"b\": [ true, \"x\" ]\n}";
+        let root = Node::parse(source, None).unwrap();
+
+        assert_eq!(root.position, at(1, 1));
+        assert_eq!(root.entries()[0].position, at(2, 3));
+        assert_eq!(root.field("a").unwrap().position, at(2, 8));
+        assert_eq!(root.entries()[1].position, at(3, 3));
+
+        let items = root.field("b").unwrap().items();
+        assert_eq!(items[0].position, at(3, 10));
+        assert_eq!(items[1].position, at(3, 16));
+    }
+
+    #[test]
+    fn columns_are_counted_in_bytes() {
+        let root = Node::parse("{ \"é\": \"\\u00e9\", \"k\": 0 }", None).unwrap();
+
+        assert_eq!(root.entries()[0].key, "é");
+        assert_eq!(root.field("é").unwrap().to_value(), sj::json!("é"));
+        assert_eq!(root.entries()[1].position, at(1, 19));
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        let root = Node::parse(r#"["a\"b\\c\n", "\ud83d\ude00"]"#, None).unwrap();
+        assert_eq!(root.to_value(), sj::json!(["a\"b\\c\n", "😀"]));
+    }
+
+    #[test]
+    fn parse_matches_serde_json() {
+        let source = r#"{ "a": { "b": [1, 2.5, null, false, {}] , "c": [] }, "d": "e" }"#;
+        let root = Node::parse(source, None).unwrap();
+
+        assert_eq!(root.to_value(), sj::from_str::<sj::Value>(source).unwrap());
+    }
+
+    #[test]
+    fn invalid_json_gives_up() {
+        assert!(Node::parse("{ \"a\" 1 }", None).is_none());
+        assert!(Node::parse("[1 2]", None).is_none());
+        assert!(Node::parse("\"unterminated", None).is_none());
+    }
+
+    #[test]
+    fn file_is_kept_by_every_node() {
+        let file: Rc<str> = Rc::from("included.json");
+        let root = Node::parse("{ \"a\": [1] }", Some(file.clone())).unwrap();
+
+        assert_eq!(root.field("a").unwrap().items()[0].file, Some(file));
+    }
+
+    #[test]
+    fn set_keeps_place_and_remove() {
+        let mut root = Node::from_value(&sj::json!({ "a": 1, "b": 2 }), None);
+
+        root.set(Entry {
+            key: String::from("a"),
+            position: None,
+            value: Node::from_value(&sj::json!(3), None),
+        });
+        root.set(Entry {
+            key: String::from("c"),
+            position: None,
+            value: Node::from_value(&sj::json!(4), None),
+        });
+
+        assert_eq!(root.to_value(), sj::json!({ "a": 3, "b": 2, "c": 4 }));
+        assert_eq!(
+            root.remove("b").map(|node| node.to_value()),
+            Some(sj::json!(2))
+        );
+        assert!(root.remove("b").is_none());
+    }
+}
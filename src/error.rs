@@ -0,0 +1,125 @@
+use super::launcher::CreateProcessError;
+use super::rules::FindCommandError;
+use super::template::TemplateError;
+use super::{ReadRulesError, WriteRulesError};
+
+// ----------------------------------------------------------------------------
+// ArgumentError
+// ----------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum ArgumentError {
+    MissingFile,
+    FilePathNotUnicode,
+    MissingRulesPath,
+    MissingOutputPath,
+}
+
+impl std::fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgumentError::MissingFile => write!(f, "No file to open was given as an argument."),
+            ArgumentError::FilePathNotUnicode => {
+                write!(f, "Could not get the name of the target file from the file path.")
+            }
+            ArgumentError::MissingRulesPath => write!(
+                f,
+                "No argument or environment variable was given that points to the fassoc rules file."
+            ),
+            ArgumentError::MissingOutputPath => write!(f, "No path to write the converted rules file to was given."),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// ProxyError
+// ----------------------------------------------------------------------------
+
+/// Every way in which fassoc-proxy can fail to open a file. Each category has
+/// its own exit code, so that wrapper scripts can tell them apart.
+#[derive(Debug)]
+pub enum ProxyError {
+    /// Exit code 2, the command line arguments were invalid or incomplete.
+    Arguments(ArgumentError),
+    /// Exit code 3, the rules file could not be read or parsed.
+    ReadRules(ReadRulesError),
+    /// Exit code 4, no command could be selected for the file.
+    FindCommand(String, FindCommandError),
+    /// Exit code 5, the placeholders of the selected command could not be
+    /// substituted.
+    Template(String, TemplateError),
+    /// Exit code 6, the process of the selected command could not be created.
+    CreateProcess(CreateProcessError),
+    /// Exit code 7, fassoc-proxy has been launching itself in a loop.
+    DepthExceeded(usize, usize),
+    /// Exit code 8, the converted rules file could not be written.
+    WriteRules(WriteRulesError),
+}
+
+impl ProxyError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ProxyError::Arguments(_) => 2,
+            ProxyError::ReadRules(_) => 3,
+            ProxyError::FindCommand(_, _) => 4,
+            ProxyError::Template(_, _) => 5,
+            ProxyError::CreateProcess(_) => 6,
+            ProxyError::DepthExceeded(_, _) => 7,
+            ProxyError::WriteRules(_) => 8,
+        }
+    }
+}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::Arguments(e) => write!(f, "Invalid arguments: {}", e),
+            ProxyError::ReadRules(e) => write!(f, "Failure when reading fassoc rules ({})", e),
+            ProxyError::FindCommand(file, e) => write!(
+                f,
+                "Could not find a suitable command for the file \"{}\", because: {}",
+                file, e
+            ),
+            ProxyError::Template(command, e) => write!(
+                f,
+                "Could not substitute the placeholders of the command \"{}\", because: {}",
+                command, e
+            ),
+            ProxyError::CreateProcess(e) => {
+                write!(f, "Error when attempting to create process: {}", e)
+            }
+            ProxyError::DepthExceeded(depth, max_depth) => write!(
+                f,
+                "Refusing to open the file, because fassoc-proxy has been launched by itself \
+                 {} times in a row, exceeding the maximum depth of {}. \
+                 This is likely a loop in the rules.",
+                depth, max_depth
+            ),
+            ProxyError::WriteRules(e) => write!(f, "Failure when writing fassoc rules ({})", e),
+        }
+    }
+}
+
+impl From<ArgumentError> for ProxyError {
+    fn from(error: ArgumentError) -> Self {
+        ProxyError::Arguments(error)
+    }
+}
+
+impl From<ReadRulesError> for ProxyError {
+    fn from(error: ReadRulesError) -> Self {
+        ProxyError::ReadRules(error)
+    }
+}
+
+impl From<WriteRulesError> for ProxyError {
+    fn from(error: WriteRulesError) -> Self {
+        ProxyError::WriteRules(error)
+    }
+}
+
+impl From<CreateProcessError> for ProxyError {
+    fn from(error: CreateProcessError) -> Self {
+        ProxyError::CreateProcess(error)
+    }
+}
@@ -0,0 +1,125 @@
+use super::launcher::resolve_command_path;
+use super::rules::{
+    CandidateOutcome, CandidateTrace, Command, ConditionTrace, FindCommandError, MatchTrace,
+    Selection,
+};
+use super::template::TemplateError;
+
+/// Prints every step that was taken in order to select a command for the
+/// file, followed by the fully substituted command, if one was selected.
+pub fn print_explanation(
+    file_name: &str,
+    trace: &MatchTrace,
+    selection: Result<&Selection, &FindCommandError>,
+    command: Option<&Result<Command, TemplateError>>,
+) {
+    println!("File: {}", file_name);
+
+    if trace.mappings.is_empty() {
+        println!("Mapping: none");
+    }
+
+    for mapping in &trace.mappings {
+        if mapping == "*" {
+            println!("Mapping: \"*\" (fallback)");
+        } else if mapping.contains('/') {
+            println!("Mapping: \"{}\" (MIME type)", mapping);
+        } else {
+            println!("Mapping: \"{}\"", mapping);
+        }
+
+        let candidates = trace
+            .candidates
+            .iter()
+            .filter(|candidate| &candidate.mapping == mapping);
+
+        for (index, candidate) in candidates.enumerate() {
+            print_candidate(index, candidate);
+        }
+    }
+
+    let selection = match selection {
+        Ok(selection) => selection,
+        Err(error) => {
+            println!("No command selected: {}", error);
+            return;
+        }
+    };
+
+    let matched_by = trace
+        .candidates
+        .last()
+        .filter(|candidate| matches!(candidate.outcome, CandidateOutcome::Matched))
+        .map(|candidate| format!(" (via matcher \"{}\")", candidate.name))
+        .unwrap_or_default();
+
+    println!("Command: \"{}\"{}", selection.name, matched_by);
+
+    let command = match command {
+        Some(Ok(command)) => command,
+        Some(Err(error)) => {
+            println!("  The placeholders could not be substituted: {}", error);
+            return;
+        }
+        None => return,
+    };
+
+    println!("  path: {}", command.path);
+
+    match resolve_command_path(&command.path) {
+        Ok(resolved) => println!("  resolved path: {}", resolved.display()),
+        Err(error) => println!("  resolved path: NONE ({})", error),
+    }
+
+    println!(
+        "  arguments: {}",
+        command.arguments.as_deref().unwrap_or("NONE")
+    );
+    println!("  cwd: {}", command.cwd.as_deref().unwrap_or("NONE"));
+}
+
+fn print_candidate(index: usize, candidate: &CandidateTrace) {
+    println!(
+        "  #{} \"{}\" - {}",
+        index, candidate.name, candidate.outcome
+    );
+
+    for condition in &candidate.conditions {
+        print_condition(condition, 3);
+    }
+}
+
+/// Prints the condition, followed by the conditions within it if it is a
+/// group, e.g. "all", each level being indented by another two spaces.
+fn print_condition(condition: &ConditionTrace, level: usize) {
+    let indent = "  ".repeat(level);
+    let verdict = if condition.passed { "passed" } else { "failed" };
+
+    if condition.pattern.is_empty() {
+        println!("{}{} - {}", indent, condition.condition, verdict);
+    } else {
+        println!(
+            "{}{} \"{}\" - {}{}",
+            indent,
+            condition.condition,
+            condition.pattern,
+            verdict,
+            condition
+                .error
+                .as_ref()
+                .map(|error| format!(" ({})", error))
+                .unwrap_or_default()
+        );
+    }
+
+    let mut captures: Vec<_> = condition.captures.iter().collect();
+    captures.sort();
+
+    for (placeholder, value) in captures {
+        println!("{}  ~~{{{}}} = \"{}\"", indent, placeholder, value);
+    }
+
+    for child in &condition.children {
+        print_condition(child, level + 1);
+    }
+}
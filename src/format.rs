@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use serde_json as sj;
+
+use super::{ReadRulesError, WriteRulesError};
+
+/// The formats a rules file can be written in, which are all deserialized into
+/// the same structs, going by the extension of the rules file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RulesFormat {
+    /// ".json" or ".jsonc", and any file with another extension, since the
+    /// format was JSON before any others were supported.
+    Json,
+    /// ".toml"
+    Toml,
+    /// ".yaml" or ".yml"
+    Yaml,
+}
+
+impl std::fmt::Display for RulesFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RulesFormat::Json => write!(f, "JSON"),
+            RulesFormat::Toml => write!(f, "TOML"),
+            RulesFormat::Yaml => write!(f, "YAML"),
+        }
+    }
+}
+
+impl RulesFormat {
+    pub fn from_path(path: &Path) -> RulesFormat {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+
+        match extension.as_deref() {
+            Some("toml") => RulesFormat::Toml,
+            Some("yaml") | Some("yml") => RulesFormat::Yaml,
+            _ => RulesFormat::Json,
+        }
+    }
+
+    /// Parses the source into a generic value, which retains the order of
+    /// every key, unlike the structs that the rules are deserialized into.
+    pub fn parse(&self, source: &str) -> Result<sj::Value, ReadRulesError> {
+        match self {
+            RulesFormat::Json => sj::from_str(&strip_jsonc(source)).map_err(ReadRulesError::SjErr),
+            RulesFormat::Toml => toml::from_str(source).map_err(ReadRulesError::TomlErr),
+            RulesFormat::Yaml => serde_yaml::from_str(source).map_err(ReadRulesError::YamlErr),
+        }
+    }
+
+    pub fn serialize(&self, value: &sj::Value) -> Result<String, WriteRulesError> {
+        match self {
+            RulesFormat::Json => sj::to_string_pretty(value)
+                .map(|json| json + "\n")
+                .map_err(WriteRulesError::SjErr),
+            RulesFormat::Toml => toml::to_string_pretty(value).map_err(WriteRulesError::TomlErr),
+            RulesFormat::Yaml => serde_yaml::to_string(value).map_err(WriteRulesError::YamlErr),
+        }
+    }
+}
+
+/// Turns JSON with comments and trailing commas (JSONC) into plain JSON, by
+/// overwriting every byte of the comments and trailing commas with spaces, so
+/// that every line and column in the result is the same as in the source.
+pub fn strip_jsonc(source: &str) -> String {
+    // Line breaks within block comments are kept, so that lines don't shift.
+    fn blank(bytes: &mut [u8], index: usize) {
+        if bytes[index] != b'\n' && bytes[index] != b'\r' {
+            bytes[index] = b' ';
+        }
+    }
+
+    let mut bytes: Vec<u8> = source.as_bytes().to_vec();
+
+    // A comma that has only been followed by whitespace and comments so far,
+    // which is a trailing comma if a closing bracket or brace comes next.
+    let mut pending_comma: Option<usize> = None;
+    // The last byte that isn't whitespace or part of a comment, so that commas
+    // which don't follow a value, e.g. "[,]", are left for serde_json to reject.
+    let mut previous: u8 = b'[';
+    let mut index: usize = 0;
+
+    while index < bytes.len() {
+        match (bytes[index], bytes.get(index + 1)) {
+            (b'"', _) => {
+                pending_comma = None;
+                previous = b'"';
+                index += 1;
+
+                while index < bytes.len() {
+                    match bytes[index] {
+                        b'\\' => index += 2,
+                        b'"' => break,
+                        _ => index += 1,
+                    }
+                }
+
+                index += 1;
+            }
+            (b'/', Some(b'/')) => {
+                while index < bytes.len() && bytes[index] != b'\n' {
+                    blank(&mut bytes, index);
+                    index += 1;
+                }
+            }
+            (b'/', Some(b'*')) => {
+                let end = source[index + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |end| index + 2 + end + 2);
+
+                while index < end {
+                    blank(&mut bytes, index);
+                    index += 1;
+                }
+            }
+            (b',', _) => {
+                pending_comma = (![b'[', b'{', b','].contains(&previous)).then_some(index);
+                previous = b',';
+                index += 1;
+            }
+            (b']', _) | (b'}', _) => {
+                if let Some(comma) = pending_comma.take() {
+                    blank(&mut bytes, comma);
+                }
+
+                previous = bytes[index];
+                index += 1;
+            }
+            (byte, _) => {
+                if !byte.is_ascii_whitespace() {
+                    pending_comma = None;
+                    previous = byte;
+                }
+
+                index += 1;
+            }
+        }
+    }
+
+    // Only whole characters are overwritten, so the result is still UTF-8.
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<sj::Value, sj::Error> {
+        sj::from_str(&strip_jsonc(source))
+    }
+
+    #[test]
+    fn comments_are_blanked() {
+        let source = "{ // line\n  \"a\": /* block */ 1 }";
+        let stripped = strip_jsonc(source);
+
+        assert_eq!(stripped, "{        \n  \"a\":             1 }");
+        assert_eq!(stripped.len(), source.len());
+    }
+
+    #[test]
+    fn block_comments_keep_line_breaks() {
+        assert_eq!(strip_jsonc("/* a\r\nb */1"), "    \r\n    1");
+    }
+
+    #[test]
+    fn comment_markers_in_strings_are_kept() {
+        let source = r#"{ "url": "http://example.com/*x*/", "quote": "\"//\"" }"#;
+        assert_eq!(strip_jsonc(source), source);
+    }
+
+    #[test]
+    fn trailing_commas_are_blanked() {
+        assert_eq!(parse("[1, 2,]").unwrap(), sj::json!([1, 2]));
+        assert_eq!(
+            parse("{ \"a\": [1,], \"b\": 2, // comment\n }").unwrap(),
+            sj::json!({ "a": [1], "b": 2 })
+        );
+    }
+
+    #[test]
+    fn commas_without_a_value_are_kept() {
+        assert!(parse("[,]").is_err());
+        assert!(parse("{,}").is_err());
+        assert!(parse("[1,,]").is_err());
+    }
+
+    #[test]
+    fn unterminated_block_comment_runs_to_the_end() {
+        assert_eq!(strip_jsonc("1 /* a"), "1     ");
+    }
+}
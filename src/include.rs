@@ -0,0 +1,256 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde_json as sj;
+
+use super::document::{Entry, Node, NodeKind};
+use super::format::{self, RulesFormat};
+use super::rules::FassocRules;
+use super::ReadRulesError;
+
+/// An item of a mapping that stands for the list of candidates that it would
+/// otherwise replace, i.e. the one of the same mapping in the included rules
+/// files, e.g. [ "MyMatcher", "..." ] prepends to the inherited list.
+pub const INHERITED: &str = "...";
+
+/// Reads the rules file, along with every rules file it includes, directly or
+/// indirectly, merged into a single document.
+pub fn load_rules(path: &Path) -> Result<Node, ReadRulesError> {
+    load_file(path, None, &mut Vec::new())
+}
+
+/// Reads a rules file, and merges it on top of the rules files it includes,
+/// where `file` is the path that included files are labelled with, and `stack`
+/// is the chain of files that led to this one, in order to detect cycles.
+fn load_file(
+    path: &Path,
+    file: Option<Rc<str>>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Node, ReadRulesError> {
+    // Errors of included files are attributed to them, but errors of the files
+    // that they include in turn have already been attributed to those.
+    let attribute = |error: ReadRulesError| match file {
+        Some(_) => ReadRulesError::Included(path.to_owned(), Box::new(error)),
+        None => error,
+    };
+
+    let canonical_path: PathBuf = fs::canonicalize(path)
+        .map_err(ReadRulesError::IoErr)
+        .map_err(attribute)?;
+
+    if let Some(start) = stack
+        .iter()
+        .position(|included| included == &canonical_path)
+    {
+        let mut cycle: Vec<PathBuf> = stack[start..].to_vec();
+        cycle.push(canonical_path);
+        return Err(ReadRulesError::IncludeCycle(cycle));
+    }
+
+    let mut document: Node = parse_file(path, file.clone()).map_err(attribute)?;
+
+    let includes: Vec<String> = document
+        .remove("include")
+        .map(|include| {
+            include
+                .items()
+                .iter()
+                .filter_map(|item| match &item.kind {
+                    NodeKind::String(include) => Some(include.to_owned()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let directory: &Path = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut rules: Node = Node::object();
+
+    stack.push(canonical_path);
+
+    for include in includes {
+        let include_path: PathBuf = directory.join(include);
+        let label: Rc<str> = Rc::from(include_path.to_string_lossy().as_ref());
+
+        log::debug!("Including the rules file \"{}\"", include_path.display());
+
+        let included: Node = load_file(&include_path, Some(label), stack)?;
+        merge(&mut rules, included);
+    }
+
+    stack.pop();
+
+    merge(&mut rules, document);
+    Ok(rules)
+}
+
+/// Parses a single rules file, without following its includes. The file is
+/// also deserialized on its own, so that type errors are reported with the
+/// position they have in the file, as the merged rules have none.
+fn parse_file(path: &Path, file: Option<Rc<str>>) -> Result<Node, ReadRulesError> {
+    let format = RulesFormat::from_path(path);
+    let source: String = fs::read_to_string(path).map_err(ReadRulesError::IoErr)?;
+
+    match format {
+        RulesFormat::Json => {
+            // The positions stay the same once comments and trailing commas
+            // are stripped.
+            let source: String = format::strip_jsonc(&source);
+            sj::from_str::<FassocRules>(&source).map_err(ReadRulesError::SjErr)?;
+
+            match Node::parse(&source, file.clone()) {
+                Some(document) => Ok(document),
+                None => Ok(Node::from_value(&format.parse(&source)?, file)),
+            }
+        }
+        _ => {
+            let value: sj::Value = format.parse(&source)?;
+            sj::from_value::<FassocRules>(value.to_owned()).map_err(ReadRulesError::SjErr)?;
+
+            Ok(Node::from_value(&value, file))
+        }
+    }
+}
+
+/// Merges a rules file into the rules that it inherits, where the file takes
+/// precedence. Options, matchers and commands are replaced one by one, and so
+/// are mappings, unless they contain "...", which is replaced by the items of
+/// the inherited mapping.
+fn merge(rules: &mut Node, document: Node) {
+    for entry in into_entries(document) {
+        match entry.key.as_str() {
+            "options" | "matchers" | "commands" => match rules.field_mut(&entry.key) {
+                Some(inherited) => {
+                    for field in into_entries(entry.value) {
+                        inherited.set(field);
+                    }
+                }
+                None => rules.set(entry),
+            },
+            "mappings" => {
+                if rules.field("mappings").is_none() {
+                    rules.set(Entry {
+                        key: entry.key.to_owned(),
+                        position: entry.position,
+                        value: Node::object(),
+                    });
+                }
+
+                if let Some(inherited) = rules.field_mut("mappings") {
+                    for mapping in into_entries(entry.value) {
+                        let mapping: Entry = expand_mapping(inherited.field(&mapping.key), mapping);
+                        inherited.set(mapping);
+                    }
+                }
+            }
+            _ => rules.set(entry),
+        }
+    }
+}
+
+/// Replaces every "..." in the mapping with the items of the inherited one,
+/// or removes them if there is no inherited mapping.
+fn expand_mapping(inherited: Option<&Node>, mut mapping: Entry) -> Entry {
+    let inherited: &[Node] = inherited.map(Node::items).unwrap_or_default();
+
+    if let NodeKind::Array(items) = &mut mapping.value.kind {
+        *items = std::mem::take(items)
+            .into_iter()
+            .flat_map(|item| match &item.kind {
+                NodeKind::String(name) if name == INHERITED => inherited.to_vec(),
+                _ => vec![item],
+            })
+            .collect();
+    }
+
+    mapping
+}
+
+fn into_entries(node: Node) -> Vec<Entry> {
+    match node.kind {
+        NodeKind::Object(entries) => entries,
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(inherited: sj::Value, document: sj::Value) -> sj::Value {
+        let mut rules = Node::from_value(&inherited, None);
+        merge(&mut rules, Node::from_value(&document, None));
+        rules.to_value()
+    }
+
+    #[test]
+    fn objects_are_merged_per_key() {
+        let rules = merged(
+            sj::json!({ "commands": { "A": { "path": "a" }, "B": { "path": "b" } } }),
+            sj::json!({ "commands": { "B": { "path": "c" }, "C": { "path": "d" } } }),
+        );
+
+        assert_eq!(
+            rules,
+            sj::json!({ "commands": { "A": { "path": "a" }, "B": { "path": "c" }, "C": { "path": "d" } } })
+        );
+    }
+
+    #[test]
+    fn other_fields_are_replaced() {
+        let rules = merged(
+            sj::json!({ "on_error": "A" }),
+            sj::json!({ "on_error": "B" }),
+        );
+        assert_eq!(rules, sj::json!({ "on_error": "B" }));
+    }
+
+    #[test]
+    fn mappings_are_replaced_without_inherited() {
+        let rules = merged(
+            sj::json!({ "mappings": { "txt": ["A"], "md": ["B"] } }),
+            sj::json!({ "mappings": { "txt": ["C"] } }),
+        );
+
+        assert_eq!(
+            rules,
+            sj::json!({ "mappings": { "txt": ["C"], "md": ["B"] } })
+        );
+    }
+
+    #[test]
+    fn inherited_is_expanded() {
+        let rules = merged(
+            sj::json!({ "mappings": { "txt": ["A", "B"] } }),
+            sj::json!({ "mappings": { "txt": ["C", "...", "D"] } }),
+        );
+
+        assert_eq!(
+            rules,
+            sj::json!({ "mappings": { "txt": ["C", "A", "B", "D"] } })
+        );
+    }
+
+    #[test]
+    fn inherited_without_mapping_is_removed() {
+        let rules = merged(
+            sj::json!({}),
+            sj::json!({ "mappings": { "txt": ["...", "A"] } }),
+        );
+        assert_eq!(rules, sj::json!({ "mappings": { "txt": ["A"] } }));
+    }
+
+    #[test]
+    fn expand_mapping_keeps_other_items() {
+        let inherited = Node::from_value(&sj::json!(["A"]), None);
+        let mapping = Entry {
+            key: String::from("txt"),
+            position: None,
+            value: Node::from_value(&sj::json!(["...", "...", 1]), None),
+        };
+
+        let expanded = expand_mapping(Some(&inherited), mapping);
+        assert_eq!(expanded.value.to_value(), sj::json!(["A", "A", 1]));
+    }
+}
@@ -0,0 +1,298 @@
+/// How many lines at the start and end of a file are searched for a Vim
+/// modeline, the same as the default of Vim's 'modelines' option.
+const MODELINE_LINES: usize = 5;
+
+/// How many bytes at the start and end of a file are read in order to detect
+/// its interpreter.
+pub const DETECTION_LENGTH: usize = 4096;
+
+/// Where the interpreter of a file was detected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Shebang,
+    Vim,
+    Emacs,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Shebang => write!(f, "shebang"),
+            Source::Vim => write!(f, "vim"),
+            Source::Emacs => write!(f, "emacs"),
+        }
+    }
+}
+
+/// The interpreter or language of a script, as declared by the script itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interpreter {
+    /// The name of the interpreter, e.g. "python3" for "#!/usr/bin/python3",
+    /// or the language of a modeline, e.g. "python" for "vim: ft=python".
+    pub name: String,
+    /// The interpreter as it was written, e.g. "/usr/bin/python3", or "node"
+    /// for "#!/usr/bin/env node". The same as the name for modelines.
+    pub path: String,
+    pub source: Source,
+}
+
+impl Interpreter {
+    /// Detects the interpreter of a file from its first and last bytes, where
+    /// a shebang takes priority over an Emacs modeline, which takes priority
+    /// over a Vim modeline.
+    pub fn detect(head: &[u8], tail: &[u8]) -> Option<Interpreter> {
+        let head = String::from_utf8_lossy(head);
+        let tail = String::from_utf8_lossy(tail);
+
+        let first_lines: Vec<&str> = head.lines().take(MODELINE_LINES).collect();
+
+        let last_lines: Vec<&str> = {
+            let lines: Vec<&str> = tail.lines().collect();
+            lines[lines.len().saturating_sub(MODELINE_LINES)..].to_vec()
+        };
+
+        let shebang = first_lines
+            .first()
+            .and_then(|line| Interpreter::parse_shebang(line));
+
+        // Emacs only looks at the first line, or the second if the first is a
+        // shebang.
+        let emacs_lines = if first_lines
+            .first()
+            .is_some_and(|line| line.starts_with("#!"))
+        {
+            2
+        } else {
+            1
+        };
+
+        let emacs = || {
+            first_lines
+                .iter()
+                .take(emacs_lines)
+                .find_map(|line| Interpreter::parse_emacs_modeline(line))
+        };
+
+        let vim = || {
+            first_lines
+                .iter()
+                .chain(last_lines.iter())
+                .find_map(|line| Interpreter::parse_vim_modeline(line))
+        };
+
+        shebang.or_else(emacs).or_else(vim)
+    }
+
+    /// Parses a line such as "#!/bin/bash", "#!/usr/bin/env -S node --flag"
+    /// or "#!C:\Python\python.exe".
+    fn parse_shebang(line: &str) -> Option<Interpreter> {
+        let mut words = line.strip_prefix("#!")?.split_whitespace();
+        let program = words.next()?;
+
+        let path = if Interpreter::base_name(program) == "env" {
+            // The options of env, and any variables it sets, come before the
+            // actual interpreter, where "-u" and "-C" take an argument.
+            loop {
+                match words.next()? {
+                    "-u" | "-C" => {
+                        words.next()?;
+                    }
+                    word if !word.starts_with('-') && !word.contains('=') => break word,
+                    _ => (),
+                }
+            }
+        } else {
+            program
+        };
+
+        Some(Interpreter {
+            name: Interpreter::base_name(path).to_owned(),
+            path: path.to_owned(),
+            source: Source::Shebang,
+        })
+    }
+
+    /// Parses a line containing "-*- mode: python -*-" or "-*- python -*-".
+    fn parse_emacs_modeline(line: &str) -> Option<Interpreter> {
+        let start = line.find("-*-")? + 3;
+        let end = start + line[start..].find("-*-")?;
+        let variables = line[start..end].trim();
+
+        let mode = if variables.contains(':') {
+            variables.split(';').find_map(|variable| {
+                let (name, value) = variable.split_once(':')?;
+                name.trim()
+                    .eq_ignore_ascii_case("mode")
+                    .then(|| value.trim())
+            })?
+        } else {
+            variables
+        };
+
+        Interpreter::from_language(mode, Source::Emacs)
+    }
+
+    /// Parses a line containing "vim: set ft=python :", "vi: ft=python", or
+    /// any other form of Vim modeline that sets the filetype or syntax.
+    fn parse_vim_modeline(line: &str) -> Option<Interpreter> {
+        let start = ["vim:", "vi:", "ex:", "Vim:"]
+            .iter()
+            .flat_map(|marker| {
+                // The marker has to be preceded by whitespace, or start the line,
+                // so occurrences such as "novim:" are skipped.
+                line.match_indices(marker)
+                    .find(|(position, _)| {
+                        line[..*position]
+                            .chars()
+                            .last()
+                            .is_none_or(char::is_whitespace)
+                    })
+                    .map(|(position, _)| position + marker.len())
+            })
+            .min()?;
+
+        line[start..]
+            .split(|c: char| c.is_whitespace() || c == ':')
+            .find_map(|option| {
+                let (name, value) = option.split_once('=')?;
+                ["ft", "filetype", "syn", "syntax"]
+                    .contains(&name)
+                    .then_some(value)
+            })
+            .and_then(|language| Interpreter::from_language(language, Source::Vim))
+    }
+
+    fn from_language(language: &str, source: Source) -> Option<Interpreter> {
+        let language = language.trim();
+
+        (!language.is_empty()).then(|| Interpreter {
+            name: language.to_owned(),
+            path: language.to_owned(),
+            source,
+        })
+    }
+
+    /// The file name of a path, without the ".exe" extension, using either
+    /// kind of separator, as the script may have been written on any platform.
+    fn base_name(path: &str) -> &str {
+        let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+
+        match name.len().checked_sub(4) {
+            Some(split)
+                if name.is_char_boundary(split) && name[split..].eq_ignore_ascii_case(".exe") =>
+            {
+                &name[..split]
+            }
+            _ => name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detected(content: &str) -> Option<(String, String, Source)> {
+        Interpreter::detect(content.as_bytes(), content.as_bytes())
+            .map(|interpreter| (interpreter.name, interpreter.path, interpreter.source))
+    }
+
+    #[test]
+    fn shebangs() {
+        let cases = [
+            ("#!/bin/bash", Some(("bash", "/bin/bash"))),
+            (
+                "#! /usr/bin/python3 -u",
+                Some(("python3", "/usr/bin/python3")),
+            ),
+            ("#!/usr/bin/env node", Some(("node", "node"))),
+            ("#!/usr/bin/env -S python3 -u", Some(("python3", "python3"))),
+            ("#!/usr/bin/env -i PATH=/bin ruby", Some(("ruby", "ruby"))),
+            (
+                "#!/usr/bin/env -u HOME -C /tmp perl",
+                Some(("perl", "perl")),
+            ),
+            (
+                "#!C:\\Python\\python.EXE",
+                Some(("python", "C:\\Python\\python.EXE")),
+            ),
+            ("#!/usr/bin/env", None),
+            ("#!", None),
+            ("# !/bin/bash", None),
+        ];
+
+        for (line, expected) in cases {
+            let parsed = Interpreter::parse_shebang(line)
+                .map(|interpreter| (interpreter.name, interpreter.path));
+            let expected = expected.map(|(name, path)| (name.to_owned(), path.to_owned()));
+            assert_eq!(parsed, expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn emacs_modelines() {
+        let cases = [
+            ("# -*- mode: ruby -*-", Some("ruby")),
+            ("# -*- python -*-", Some("python")),
+            ("/* -*- coding: utf-8; Mode: C; -*- */", Some("C")),
+            ("-*- coding: utf-8 -*-", None),
+            ("-*- ruby", None),
+        ];
+
+        for (line, expected) in cases {
+            let parsed =
+                Interpreter::parse_emacs_modeline(line).map(|interpreter| interpreter.name);
+            assert_eq!(parsed.as_deref(), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn vim_modelines() {
+        let cases = [
+            ("# vim: set ft=python :", Some("python")),
+            ("vi: ts=4 filetype=lua", Some("lua")),
+            ("// Vim: syntax=cpp", Some("cpp")),
+            ("# ex:ft=sh", Some("sh")),
+            ("novim: ft=x  vim: ft=ruby", Some("ruby")),
+            ("# vim: ts=4 sw=4", None),
+            ("# novim: ft=python", None),
+        ];
+
+        for (line, expected) in cases {
+            let parsed = Interpreter::parse_vim_modeline(line).map(|interpreter| interpreter.name);
+            assert_eq!(parsed.as_deref(), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn detection_priority() {
+        let cases = [
+            (
+                "#!/bin/sh\n# -*- mode: ruby -*-\n# vim: ft=lua\n",
+                Some(("sh", Source::Shebang)),
+            ),
+            ("#!\n# -*- mode: ruby -*-\n", Some(("ruby", Source::Emacs))),
+            (
+                "# -*- mode: ruby -*-\n# vim: ft=lua\n",
+                Some(("ruby", Source::Emacs)),
+            ),
+            // Emacs only looks at the second line after a shebang.
+            ("first\n# -*- mode: ruby -*-\n", None),
+            (
+                "first\n# -*- mode: ruby -*-\n# vim: ft=lua\n",
+                Some(("lua", Source::Vim)),
+            ),
+            (
+                "a\nb\nc\nd\ne\nf\n# vim: ft=lua\n",
+                Some(("lua", Source::Vim)),
+            ),
+            ("plain text\n", None),
+        ];
+
+        for (content, expected) in cases {
+            let parsed = detected(content).map(|(name, _, source)| (name, source));
+            let expected = expected.map(|(name, source)| (name.to_owned(), source));
+            assert_eq!(parsed, expected, "{:?}", content);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use super::rules::Command;
+
+#[cfg(windows)]
+pub use super::winproc::WinApiLauncher as PlatformLauncher;
+
+#[cfg(unix)]
+pub use super::posixproc::PosixLauncher as PlatformLauncher;
+
+// ----------------------------------------------------------------------------
+// CreateProcessError
+// ----------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum CreateProcessError {
+    CommandNotExecutable(String),
+    CommandDoesNotExist(String),
+    CommandNotAbsolute(String),
+    CommandNotFound(String),
+    CommandIsProxy(String),
+    StringContainsNul(String),
+    CreationFailed(String, String),
+}
+
+impl std::fmt::Display for CreateProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CreateProcessError::CommandNotExecutable(cmd) => {
+                write!(f, "The command \"{}\" is not executable", cmd)
+            }
+            CreateProcessError::CommandDoesNotExist(cmd) => {
+                write!(f, "The command \"{}\" does not exist", cmd)
+            }
+            CreateProcessError::CommandNotAbsolute(cmd) => {
+                write!(f, "The command \"{}\" does not have an absolute path", cmd)
+            }
+            CreateProcessError::CommandNotFound(cmd) => {
+                write!(
+                    f,
+                    "The command \"{}\" could not be found in any of the PATH directories",
+                    cmd
+                )
+            }
+            CreateProcessError::CommandIsProxy(cmd) => {
+                write!(
+                    f,
+                    "The command \"{}\" refers to fassoc-proxy itself, refusing to launch it",
+                    cmd
+                )
+            }
+            CreateProcessError::StringContainsNul(string) => {
+                write!(f, "The string \"{}\" contains a null character, and cannot be passed to the process", string)
+            }
+            CreateProcessError::CreationFailed(cmd, reason) => {
+                write!(
+                    f,
+                    "The process for \"{}\" could not be created: {}",
+                    cmd, reason
+                )
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Launcher
+// ----------------------------------------------------------------------------
+
+/// The environment variable through which fassoc-proxy tells the processes
+/// it launches how deeply nested they are, so that it can refuse to continue
+/// if it ends up launching itself in a loop.
+pub const DEPTH_VARIABLE: &str = "FASSOC_PROXY_DEPTH";
+
+/// The nesting depth of this instance of fassoc-proxy, i.e. how many
+/// instances of it came before this one, as told by `DEPTH_VARIABLE`.
+pub fn current_depth() -> usize {
+    env::var(DEPTH_VARIABLE)
+        .ok()
+        .and_then(|depth| depth.parse().ok())
+        .unwrap_or(0)
+}
+
+/// A backend capable of turning a selected `Command` into a running process.
+/// Each platform provides one implementation, exported as `PlatformLauncher`.
+pub trait Launcher {
+    /// Backend specific information about the process that was created.
+    type Process: std::fmt::Debug;
+
+    fn launch(&self, command: &Command) -> Result<Self::Process, CreateProcessError>;
+}
+
+/// Checks that the command path exists, is absolute, is executable, and
+/// isn't fassoc-proxy itself.
+///
+/// The executable check is what protects against accidentally forkbombing
+/// the machine, by pointing a command at a file that is itself associated
+/// with fassoc-proxy, making it launch itself over and over. The depth that
+/// is passed on through `DEPTH_VARIABLE` catches any loop that remains.
+pub fn check_command_path(path: &str) -> Result<(), CreateProcessError> {
+    let command_path = Path::new(path);
+
+    if !command_path.exists() {
+        return Err(CreateProcessError::CommandDoesNotExist(path.to_owned()));
+    }
+
+    if !is_executable(command_path) {
+        return Err(CreateProcessError::CommandNotExecutable(path.to_owned()));
+    }
+
+    if !command_path.is_absolute() {
+        return Err(CreateProcessError::CommandNotAbsolute(path.to_owned()));
+    }
+
+    let current_exe = env::current_exe().and_then(|exe| exe.canonicalize()).ok();
+
+    if current_exe.is_some() && command_path.canonicalize().ok() == current_exe {
+        return Err(CreateProcessError::CommandIsProxy(path.to_owned()));
+    }
+
+    Ok(())
+}
+
+/// Resolves the command path into the absolute path of an executable. An
+/// absolute path is used as is, whereas a bare name such as "nvim" is looked
+/// up in the directories of the PATH environment variable (with ".exe" added
+/// on Windows, if it has no extension), skipping anything not executable.
+///
+/// Unlike a shell, PATHEXT is not consulted, so a bare name never resolves
+/// to a ".cmd" or ".bat" file, and neither does a name that has either of
+/// those extensions. Batch files are run by cmd.exe, which interprets
+/// characters such as "&" in the file name, so they have to be run through
+/// cmd.exe explicitly instead.
+pub fn resolve_command_path(path: &str) -> Result<PathBuf, CreateProcessError> {
+    let command_path = Path::new(path);
+
+    if command_path.is_absolute() {
+        return check_command_path(path).map(|_| command_path.to_path_buf());
+    }
+
+    // Relative paths such as "bin\app.exe" would depend on the working
+    // directory of whatever launched fassoc-proxy, so only bare names are
+    // looked up.
+    if command_path.components().count() != 1 || command_path.file_name().is_none() {
+        return Err(CreateProcessError::CommandNotAbsolute(path.to_owned()));
+    }
+
+    // Only ".exe" is tried, rather than every extension in PATHEXT, see above.
+    let candidate_name: String = if cfg!(windows) && command_path.extension().is_none() {
+        format!("{}.exe", path)
+    } else {
+        path.to_owned()
+    };
+
+    let search_paths = env::var_os("PATH").unwrap_or_default();
+
+    env::split_paths(&search_paths)
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| dir.join(&candidate_name))
+        .find(|candidate| candidate.is_file() && is_executable(candidate))
+        .ok_or_else(|| CreateProcessError::CommandNotFound(path.to_owned()))
+        .and_then(|candidate| check_command_path(&candidate.to_string_lossy()).map(|_| candidate))
+}
+
+#[cfg(windows)]
+fn is_executable(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exe"))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
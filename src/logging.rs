@@ -11,30 +11,27 @@ impl log::Log for MainLogger {
 
     fn log(&self, record: &log::Record) {
         if self.enabled(record.metadata()) {
-            let current_exe_path = env::current_exe().unwrap();
-            let exe_dir_path = current_exe_path.parent().unwrap();
-            let log_file_path = exe_dir_path.join("fassoc-proxy.log");
-
-            let mut log_file = fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(log_file_path)
-                .unwrap();
-
             let log_time = chrono::Local::now().format("%d-%m-%y %H:%M:%S");
 
-            let outmsg = format!(
-                "[{}] - {} - {}",
-                log_time.to_string(),
-                record.level(),
-                record.args()
-            );
-
-            println!("{}", outmsg);
-
-            match writeln!(log_file, "{}", outmsg) {
-                Ok(val) => val,
-                Err(_) => (),
+            let outmsg = format!("[{}] - {} - {}", log_time, record.level(), record.args());
+
+            writeln!(std::io::stdout(), "{}", outmsg).ok();
+
+            // The log file lives next to the executable, but if that location
+            // isn't writable, the message is only written to the console.
+            let log_file = env::current_exe()
+                .ok()
+                .and_then(|exe_path| exe_path.parent().map(|dir| dir.join("fassoc-proxy.log")))
+                .and_then(|log_file_path| {
+                    fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(log_file_path)
+                        .ok()
+                });
+
+            if let Some(mut log_file) = log_file {
+                writeln!(log_file, "{}", outmsg).ok();
             }
         }
     }
@@ -4,78 +4,239 @@
 use std::fs;
 
 use serde_json as sj;
-use std::{env, io::Read, path::Path};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 
 mod logging;
 use logging::MAIN_LOGGER;
 
 mod rules;
-use rules::{Command, FassocRules};
+use rules::{Command, CommandSearch, FassocRules, PatternError, Selection};
 
-use crate::winproc::invoke_command;
+mod error;
+use error::{ArgumentError, ProxyError};
 
+mod explain;
+
+mod interpreter;
+
+mod validate;
+use validate::{Diagnostic, Severity};
+
+mod format;
+use format::RulesFormat;
+
+mod document;
+use document::Node;
+
+mod include;
+
+mod template;
+use template::{TemplateError, Variables};
+
+mod launcher;
+use launcher::{
+    resolve_command_path, CreateProcessError, Launcher, PlatformLauncher, DEPTH_VARIABLE,
+};
+
+#[cfg(windows)]
 mod winproc;
 
+#[cfg(unix)]
+mod posixproc;
+
+/// How deeply instances of fassoc-proxy may be nested, unless the rules file
+/// specifies otherwise.
+const DEFAULT_MAX_DEPTH: usize = 3;
+
 #[derive(Debug)]
-enum ReadRulesError {
+pub enum ReadRulesError {
     SjErr(sj::Error),
+    TomlErr(toml::de::Error),
+    YamlErr(serde_yaml::Error),
     IoErr(std::io::Error),
+    Invalid(Vec<Diagnostic>),
+    /// The rules files include each other, starting and ending with the same
+    /// file.
+    IncludeCycle(Vec<PathBuf>),
+    /// An error in an included rules file, rather than the one that was given.
+    Included(PathBuf, Box<ReadRulesError>),
 }
 
 impl std::fmt::Display for ReadRulesError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ReadRulesError::SjErr(e) => write!(f, "Serde JSON Error: {}", e),
+            ReadRulesError::TomlErr(e) => write!(f, "TOML Error: {}", e),
+            ReadRulesError::YamlErr(e) => write!(f, "YAML Error: {}", e),
             ReadRulesError::IoErr(e) => write!(f, "IO Error: {}", e),
+            ReadRulesError::IncludeCycle(cycle) => {
+                let cycle: Vec<String> = cycle
+                    .iter()
+                    .map(|path| format!("\"{}\"", path.display()))
+                    .collect();
+                write!(
+                    f,
+                    "The rules files include each other in a cycle: {}",
+                    cycle.join(" -> ")
+                )
+            }
+            ReadRulesError::Included(path, e) => write!(
+                f,
+                "In the included rules file \"{}\": {}",
+                path.display(),
+                e
+            ),
+            ReadRulesError::Invalid(diagnostics) => {
+                write!(f, "{} error(s) in the rules", diagnostics.len())?;
+
+                // Messages can span several lines, e.g. those of RegEx errors.
+                for diagnostic in diagnostics {
+                    write!(
+                        f,
+                        "\n    {}",
+                        diagnostic.to_string().replace('\n', "\n    ")
+                    )?;
+                }
+
+                Ok(())
+            }
         }
     }
 }
 
-fn read_fassoc_rules(path: String) -> Result<FassocRules, ReadRulesError> {
-    let fassoc_rules: FassocRules = sj::from_str(
-        fs::read_to_string(path)
-            .map_err(|e| ReadRulesError::IoErr(e))?
-            .as_str(),
-    )
-    .map_err(|e| ReadRulesError::SjErr(e))?;
+#[derive(Debug)]
+pub enum WriteRulesError {
+    SjErr(sj::Error),
+    TomlErr(toml::ser::Error),
+    YamlErr(serde_yaml::Error),
+    IoErr(std::io::Error),
+    /// The rules would not read back the same in the format, e.g. because TOML
+    /// has no equivalent of null.
+    Lossy(RulesFormat),
+}
 
-    Ok(fassoc_rules)
+impl std::fmt::Display for WriteRulesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WriteRulesError::SjErr(e) => write!(f, "Serde JSON Error: {}", e),
+            WriteRulesError::TomlErr(e) => write!(f, "TOML Error: {}", e),
+            WriteRulesError::YamlErr(e) => write!(f, "YAML Error: {}", e),
+            WriteRulesError::IoErr(e) => write!(f, "IO Error: {}", e),
+            WriteRulesError::Lossy(format) => {
+                write!(
+                    f,
+                    "The rules cannot be represented in {} without changing them.",
+                    format
+                )
+            }
+        }
+    }
 }
 
-fn subst_arg_placeholders(rules: FassocRules, args: Vec<String>) -> FassocRules {
-    let mut subst_rules: FassocRules = rules;
+fn read_fassoc_rules(path: String) -> Result<FassocRules, ReadRulesError> {
+    read_fassoc_rules_with_notes(path).map(|(fassoc_rules, _)| fassoc_rules)
+}
 
-    for (index, argument) in args.iter().enumerate() {
-        let placeholder = format!("~~${}", index);
+/// Reads the rules, along with the notes of the validator, which are only of
+/// interest when validating the rules file, rather than on every read.
+fn read_fassoc_rules_with_notes(
+    path: String,
+) -> Result<(FassocRules, Vec<Diagnostic>), ReadRulesError> {
+    let rules: Node = include::load_rules(Path::new(&path))?;
+    let fassoc_rules: FassocRules =
+        sj::from_value(rules.to_value()).map_err(ReadRulesError::SjErr)?;
 
-        for command in subst_rules.commands.values_mut() {
-            command.path = command.path.replace(&placeholder, argument);
+    // The patterns are compiled here, so that they are only compiled once, and
+    // the validator reports the invalid ones along with every other mistake.
+    let pattern_errors: Vec<PatternError> =
+        fassoc_rules.compile_patterns().err().unwrap_or_default();
+    let diagnostics: Vec<Diagnostic> = validate::validate(&rules, pattern_errors);
 
-            command.arguments = command
-                .arguments
-                .to_owned()
-                .map(|arg| arg.replace(&placeholder, argument));
+    // Warnings and notes don't prevent the rules from being used, but errors do.
+    let (errors, rest): (Vec<Diagnostic>, Vec<Diagnostic>) = diagnostics
+        .into_iter()
+        .partition(|diagnostic| diagnostic.severity == Severity::Error);
 
-            command.cwd = command
-                .cwd
-                .to_owned()
-                .map(|cwd| cwd.replace(&placeholder, argument));
+    let (warnings, notes): (Vec<Diagnostic>, Vec<Diagnostic>) = rest
+        .into_iter()
+        .partition(|diagnostic| diagnostic.severity == Severity::Warning);
 
-            command.extras = command.extras.to_owned().map(|mut extras| {
-                extras.title = extras.title.map(|str| str.replace(&placeholder, argument));
-                extras.desktop = extras
-                    .desktop
-                    .map(|str| str.replace(&placeholder, argument));
-                extras
-            });
+    for warning in warnings {
+        log::warn!("{}", warning);
+    }
+
+    if !errors.is_empty() {
+        return Err(ReadRulesError::Invalid(errors));
+    }
+
+    Ok((fassoc_rules, notes))
+}
+
+fn subst_arg_placeholders(
+    command: &Command,
+    variables: &Variables,
+) -> Result<Command, TemplateError> {
+    let mut command: Command = command.clone();
+    let subst = |source: String| template::substitute(&source, variables);
+
+    command.path = subst(command.path)?;
+    command.arguments = command.arguments.map(subst).transpose()?;
+    command.cwd = command.cwd.map(subst).transpose()?;
+
+    if let Some(environment) = command.environment.as_mut() {
+        for values in [
+            &mut environment.set,
+            &mut environment.prepend,
+            &mut environment.append,
+        ] {
+            for value in values.iter_mut().flatten().map(|(_, value)| value) {
+                *value = subst(std::mem::take(value))?;
+            }
         }
     }
 
-    subst_rules
+    if let Some(extras) = command.extras.as_mut() {
+        extras.title = extras.title.take().map(subst).transpose()?;
+        extras.desktop = extras.desktop.take().map(subst).transpose()?;
+    }
+
+    Ok(command)
+}
+
+/// Replaces the path of the command with the absolute path it resolves to,
+/// which differs when the path is a bare name that is looked up in PATH.
+fn resolve_command(mut command: Command) -> Result<Command, CreateProcessError> {
+    let resolved = resolve_command_path(&command.path)?;
+
+    if resolved.as_path() != Path::new(&command.path) {
+        log::info!(
+            "Resolved the command path \"{}\" to \"{}\"",
+            command.path,
+            resolved.display()
+        );
+        command.path = resolved.to_string_lossy().into_owned();
+    }
+
+    Ok(command)
+}
+
+#[derive(PartialEq)]
+enum Mode {
+    /// Open the file with the command selected by the rules.
+    Open,
+    /// Print how a command would be selected for the file, without opening it.
+    Explain,
+    /// Check the rules file for mistakes, without opening anything.
+    Validate,
+    /// Rewrite the rules file in another format, without opening anything.
+    Convert,
 }
 
 fn main() {
-    log::set_logger(&MAIN_LOGGER).unwrap();
+    log::set_logger(&MAIN_LOGGER).ok();
 
     if cfg!(debug_assertions) {
         log::set_max_level(log::LevelFilter::Debug);
@@ -87,74 +248,287 @@ fn main() {
 
     log::debug!("Received command line arguments: {:?}", cli_args);
 
-    if cli_args.len() < 2 {
-        log::error!("Exiting because the argument length {} < 2", cli_args.len());
-        std::process::exit(1);
-    }
+    // The mode is stripped from the arguments, so that the placeholders refer
+    // to the same arguments regardless of the mode, e.g. ~~$1 is the file.
+    let (mode, proxy_args): (Mode, Vec<String>) = match cli_args.get(1).map(String::as_str) {
+        Some("explain") | Some("--dry-run") => {
+            let mut proxy_args = cli_args.to_owned();
+            proxy_args.remove(1);
+            (Mode::Explain, proxy_args)
+        }
+        Some("validate") => {
+            let mut proxy_args = cli_args.to_owned();
+            proxy_args.remove(1);
+            (Mode::Validate, proxy_args)
+        }
+        Some("convert") => {
+            let mut proxy_args = cli_args.to_owned();
+            proxy_args.remove(1);
+            (Mode::Convert, proxy_args)
+        }
+        _ => (Mode::Open, cli_args.to_owned()),
+    };
 
-    let target_file_path = Path::new(&cli_args[1]);
+    if mode != Mode::Open {
+        // The explanation, validation or conversion is the output, so the
+        // debug messages that would otherwise be printed alongside it are
+        // silenced, leaving only warnings and errors.
+        log::set_max_level(log::LevelFilter::Warn);
 
-    let target_file_name = match target_file_path.to_str() {
-        Some(file_name) => file_name,
-        None => {
-            log::error!("Could not get the name of the target file from the file path!");
-            panic!()
+        #[cfg(windows)]
+        winproc::attach_parent_console();
+    }
+
+    let exit_code = match run(&mode, proxy_args) {
+        Ok(()) => 0,
+        Err(error) => {
+            log::error!("{}", error);
+            error.exit_code()
         }
     };
 
-    let fassoc_rules_path: String = if cli_args.len() >= 3 {
-        cli_args[2].clone()
+    if cfg!(debug_assertions) && mode == Mode::Open {
+        println!("Press enter to close the debug message console.");
+        std::io::stdin().read_line(&mut String::new()).ok();
+    }
+
+    std::process::exit(exit_code);
+}
+
+fn run(mode: &Mode, proxy_args: Vec<String>) -> Result<(), ProxyError> {
+    if *mode == Mode::Validate {
+        return validate_fassoc_rules(proxy_args);
+    }
+
+    if *mode == Mode::Convert {
+        return convert_fassoc_rules(proxy_args);
+    }
+
+    if proxy_args.len() < 2 {
+        return Err(ArgumentError::MissingFile.into());
+    }
+
+    let target_file_path = Path::new(&proxy_args[1]);
+
+    let target_file_name = target_file_path
+        .to_str()
+        .ok_or(ArgumentError::FilePathNotUnicode)?;
+
+    let fassoc_rules_path: String = if proxy_args.len() >= 3 {
+        proxy_args[2].clone()
     } else {
-        match env::var("FASSOC_RULES_PATH") {
-            Ok(val) => val,
-            Err(_) => {
-                log::error!("No argument or environment variable was given that points to the fassoc rules file.");
-                panic!()
-            }
-        }
+        env::var("FASSOC_RULES_PATH").map_err(|_| ArgumentError::MissingRulesPath)?
     };
 
-    let fassoc_rules = match read_fassoc_rules(fassoc_rules_path) {
-        Ok(rules) => subst_arg_placeholders(rules, cli_args.to_owned()),
-        Err(error) => {
-            log::error!("Failure when reading fassoc rules ({})", error);
-            panic!();
+    let fassoc_rules: FassocRules = read_fassoc_rules(fassoc_rules_path)?;
+
+    if *mode == Mode::Open {
+        let depth = launcher::current_depth();
+
+        let max_depth = fassoc_rules
+            .options
+            .as_ref()
+            .and_then(|o| o.max_depth)
+            .unwrap_or(DEFAULT_MAX_DEPTH);
+
+        if depth > max_depth {
+            return Err(ProxyError::DepthExceeded(depth, max_depth));
         }
+
+        // Every process launched from here on inherits the incremented depth.
+        env::set_var(DEPTH_VARIABLE, (depth + 1).to_string());
+    }
+
+    let mut search: CommandSearch = fassoc_rules.search_commands(target_file_path);
+    let mut variables = Variables::for_file(proxy_args.to_owned(), target_file_path);
+
+    if let Some(undefined) = fassoc_rules
+        .options
+        .as_ref()
+        .and_then(|o| o.undefined_variables)
+    {
+        variables.undefined = undefined;
+    }
+
+    if *mode == Mode::Explain {
+        let selection = search.next_command();
+
+        let suitable_command: Option<Result<Command, TemplateError>> =
+            selection.as_ref().ok().map(|selection| {
+                subst_arg_placeholders(
+                    selection.command,
+                    &selection_variables(selection, &variables),
+                )
+            });
+
+        explain::print_explanation(
+            target_file_name,
+            &search.trace,
+            selection.as_ref(),
+            suitable_command.as_ref(),
+        );
+
+        return Ok(());
+    }
+
+    let result = launch_suitable_command(&mut search, &variables, target_file_name);
+
+    if let (Err(error), Some(on_error)) = (&result, &fassoc_rules.on_error) {
+        launch_on_error_command(&fassoc_rules, on_error, &variables, error);
+    }
+
+    result
+}
+
+/// Reads the rules file given as the only argument, or pointed to by the
+/// environment variable, where any problems are logged as it is read.
+fn validate_fassoc_rules(proxy_args: Vec<String>) -> Result<(), ProxyError> {
+    let fassoc_rules_path: String = match proxy_args.get(1) {
+        Some(path) => path.to_owned(),
+        None => env::var("FASSOC_RULES_PATH").map_err(|_| ArgumentError::MissingRulesPath)?,
     };
 
-    let suitable_command: &Command = match fassoc_rules.find_suitable_command(target_file_path) {
-        Ok(command) => command,
-        Err(error) => {
-            log::error!(
-                "Could not find a suitable command for the file \"{}\", because: {}",
-                target_file_name,
-                error
-            );
-            panic!("");
-        }
+    let (_, notes) = read_fassoc_rules_with_notes(fassoc_rules_path.to_owned())?;
+
+    for note in notes {
+        println!("{}", note);
+    }
+
+    println!("The rules file \"{}\" is valid.", fassoc_rules_path);
+
+    Ok(())
+}
+
+/// Rewrites the rules file given as the first argument into the file given as
+/// the second, where the format of each is chosen by its extension. The rules
+/// are validated first, and the result is read back to ensure nothing changed.
+fn convert_fassoc_rules(proxy_args: Vec<String>) -> Result<(), ProxyError> {
+    let (input_path, output_path) = match (proxy_args.get(1), proxy_args.get(2)) {
+        (Some(input_path), Some(output_path)) => (input_path, output_path),
+        (None, _) => return Err(ArgumentError::MissingRulesPath.into()),
+        (Some(_), None) => return Err(ArgumentError::MissingOutputPath.into()),
     };
 
-    log::debug!(
-        "Creating process, path: \"{}\", args: \"{}\"",
-        suitable_command.path.to_owned(),
-        suitable_command
-            .arguments
-            .to_owned()
-            .unwrap_or(String::from("NONE"))
+    let input_format = RulesFormat::from_path(Path::new(input_path));
+    let output_format = RulesFormat::from_path(Path::new(output_path));
+
+    read_fassoc_rules(input_path.to_owned())?;
+    let source: String = fs::read_to_string(input_path).map_err(ReadRulesError::IoErr)?;
+
+    // The generic value is converted, rather than the deserialized rules, as
+    // it retains the order of the keys, and leaves out absent fields. Only the
+    // file itself is converted, the files that it includes are left as is.
+    let value: sj::Value = input_format.parse(&source)?;
+    let output: String = output_format.serialize(&value)?;
+
+    if output_format.parse(&output).ok().as_ref() != Some(&value) {
+        return Err(WriteRulesError::Lossy(output_format).into());
+    }
+
+    fs::write(output_path, output).map_err(WriteRulesError::IoErr)?;
+    println!(
+        "Converted the rules file \"{}\" to {} in \"{}\".",
+        input_path, output_format, output_path
     );
 
-    match invoke_command(&suitable_command) {
-        Ok(process_info) => {
-            log::debug!("Process created, information: {:?}", process_info)
-        }
+    Ok(())
+}
 
-        Err(error) => {
-            log::error!("Error when attempting to create process: {}", error)
+/// The variables for a selected command, which include the groups captured by
+/// the matcher that selected it.
+fn selection_variables(selection: &Selection, variables: &Variables) -> Variables {
+    let mut variables: Variables = variables.clone();
+    variables.named.extend(selection.captures.to_owned());
+    variables
+}
+
+/// Launches the commands selected by the search one after another, until one
+/// of them launches successfully. When none of them do, the last failure is
+/// returned, or why no command could be selected if none were.
+fn launch_suitable_command(
+    search: &mut CommandSearch,
+    variables: &Variables,
+    target_file_name: &str,
+) -> Result<(), ProxyError> {
+    let mut last_failure: Option<ProxyError> = None;
+
+    loop {
+        let selection = match search.next_command() {
+            Ok(selection) => selection,
+            Err(error) => {
+                return Err(last_failure.unwrap_or_else(|| {
+                    ProxyError::FindCommand(target_file_name.to_owned(), error)
+                }))
+            }
+        };
+
+        let launched = subst_arg_placeholders(
+            selection.command,
+            &selection_variables(&selection, variables),
+        )
+        .map_err(|error| ProxyError::Template(selection.name.to_owned(), error))
+        .and_then(launch_command);
+
+        match launched {
+            Ok(()) => return Ok(()),
+            Err(error) => {
+                log::warn!(
+                    "Failed to launch the command \"{}\", moving on to the next candidate. {}",
+                    selection.name,
+                    error
+                );
+
+                last_failure = Some(error);
+            }
         }
     }
+}
 
-    if cfg!(debug_assertions) {
-        println!("Press enter to close the debug message console.");
-        std::io::stdin().read(&mut [0u8]).unwrap_or(1);
+/// Launches the `on_error` command of the rules, where the placeholder
+/// ~~{error} is substituted with the reason the file could not be opened.
+fn launch_on_error_command(
+    fassoc_rules: &FassocRules,
+    on_error: &str,
+    variables: &Variables,
+    error: &ProxyError,
+) {
+    let command = match fassoc_rules.commands.get(on_error) {
+        Some(command) => command,
+        None => {
+            log::error!("The on_error command \"{}\" does not exist.", on_error);
+            return;
+        }
+    };
+
+    let mut variables: Variables = variables.clone();
+    variables
+        .named
+        .insert(String::from("error"), error.to_string());
+
+    let launched = subst_arg_placeholders(command, &variables)
+        .map_err(|error| ProxyError::Template(on_error.to_owned(), error))
+        .and_then(launch_command);
+
+    if let Err(error) = launched {
+        log::error!(
+            "Failed to launch the on_error command \"{}\". {}",
+            on_error,
+            error
+        );
     }
 }
+
+fn launch_command(command: Command) -> Result<(), ProxyError> {
+    let command: Command = resolve_command(command)?;
+
+    log::debug!(
+        "Creating process, path: \"{}\", args: \"{}\"",
+        command.path.to_owned(),
+        command.arguments.to_owned().unwrap_or(String::from("NONE"))
+    );
+
+    let process_info = PlatformLauncher.launch(&command)?;
+    log::debug!("Process created, information: {:?}", process_info);
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::os::unix::process::CommandExt;
+use std::process::{self, Child};
+
+use super::launcher::{check_command_path, CreateProcessError, Launcher};
+use super::rules::Command;
+
+/// Launches commands through `fork`/`exec` (by way of `std::process`). The
+/// WinAPI specific parts of a `Command` have no POSIX equivalent, and are
+/// ignored with a warning.
+#[derive(Debug, Default)]
+pub struct PosixLauncher;
+
+impl Launcher for PosixLauncher {
+    type Process = Child;
+
+    fn launch(&self, rule: &Command) -> Result<Child, CreateProcessError> {
+        warn_ignored_fields(rule);
+        check_command_path(&rule.path)?;
+
+        let strings = [Some(&rule.path), rule.arguments.as_ref(), rule.cwd.as_ref()];
+
+        if let Some(string) = strings.into_iter().flatten().find(|s| s.contains('\0')) {
+            return Err(CreateProcessError::StringContainsNul(string.to_owned()));
+        }
+
+        let mut process = process::Command::new(&rule.path);
+
+        // The arguments string follows the Windows convention of including
+        // the program itself as the first argument, so it becomes argv[0].
+        let mut argv = rule
+            .arguments
+            .as_deref()
+            .map(split_arguments)
+            .unwrap_or_default()
+            .into_iter();
+
+        if let Some(arg0) = argv.next() {
+            process.arg0(arg0);
+        }
+
+        process.args(argv);
+
+        if let Some(cwd) = &rule.cwd {
+            process.current_dir(cwd);
+        }
+
+        if let Some(environment) = &rule.environment {
+            process.env_clear();
+            process.envs(environment.resolve());
+        }
+
+        process.spawn().map_err(|error| {
+            CreateProcessError::CreationFailed(rule.path.to_owned(), error.to_string())
+        })
+    }
+}
+
+fn warn_ignored_fields(rule: &Command) {
+    let ignored = [
+        ("process_attributes", rule.process_attributes.is_some()),
+        ("thread_attributes", rule.thread_attributes.is_some()),
+        ("inherit_handles", rule.inherit_handles.is_some()),
+        ("creation_flags", rule.creation_flags.is_some()),
+        ("extras", rule.extras.is_some()),
+    ];
+
+    for (field, _) in ignored.iter().filter(|(_, present)| *present) {
+        log::warn!(
+            "The \"{}\" field of the command \"{}\" is Windows specific, and is ignored on this platform.",
+            field,
+            rule.path
+        );
+    }
+}
+
+/// Splits an arguments string into individual arguments. Whitespace separates
+/// arguments, single quotes group text literally, and double quotes group text
+/// while still allowing `\"` and `\\` escapes inside of them.
+fn split_arguments(arguments: &str) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = arguments.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(argument) = current.take() {
+                    result.push(argument);
+                }
+            }
+
+            '\'' => {
+                let argument = current.get_or_insert_with(String::new);
+                argument.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+
+            '"' => {
+                let argument = current.get_or_insert_with(String::new);
+
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' if matches!(chars.peek(), Some('"') | Some('\\')) => {
+                            argument.extend(chars.next());
+                        }
+                        c => argument.push(c),
+                    }
+                }
+            }
+
+            '\\' if matches!(chars.peek(), Some(c) if c.is_whitespace() || "\"'\\".contains(*c)) => {
+                current.get_or_insert_with(String::new).extend(chars.next());
+            }
+
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    result.extend(current);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitespace_separates() {
+        assert_eq!(split_arguments("  a b\t\tc\n"), ["a", "b", "c"]);
+        assert!(split_arguments("   ").is_empty());
+    }
+
+    #[test]
+    fn single_quotes_are_literal() {
+        assert_eq!(split_arguments(r#"'a b' 'c\"d'"#), ["a b", r#"c\"d"#]);
+    }
+
+    #[test]
+    fn double_quotes_allow_escapes() {
+        assert_eq!(split_arguments(r#""a \"b\" \\ \n""#), [r#"a "b" \ \n"#]);
+    }
+
+    #[test]
+    fn empty_quotes_are_an_argument() {
+        assert_eq!(split_arguments(r#"a "" ''"#), ["a", "", ""]);
+    }
+
+    #[test]
+    fn quotes_join_adjacent_text() {
+        assert_eq!(split_arguments(r#"--name="a b"'c'd"#), ["--name=a bcd"]);
+    }
+
+    #[test]
+    fn backslash_escapes_outside_quotes() {
+        assert_eq!(split_arguments(r"a\ b c\'d e\\f"), ["a b", "c'd", r"e\f"]);
+        assert_eq!(split_arguments(r"C:\path\file"), [r"C:\path\file"]);
+    }
+
+    #[test]
+    fn unterminated_quotes_run_to_the_end() {
+        assert_eq!(split_arguments("'a b"), ["a b"]);
+        assert_eq!(split_arguments("\"a b"), ["a b"]);
+    }
+}
@@ -1,10 +1,21 @@
-use std::{collections::HashMap, path::Path};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
 
+use globset as gs;
 use regex as re;
 use serde_json as sj;
 
 use serde::{Deserialize, Serialize};
 
+use super::interpreter::{self, Interpreter};
+use super::launcher::DEPTH_VARIABLE;
+use super::template::{substitute, TemplateError, UndefinedVariables, Variables};
+
 // ----------------------------------------------------------------------------
 // FassocRules
 // ----------------------------------------------------------------------------
@@ -22,190 +33,1850 @@ impl std::fmt::Display for FindCommandError {
             FindCommandError::CannotConvertPath => {
                 write!(f, "Could not convert the path of the file into a string.",)
             }
-            FindCommandError::NoMappingFound => write!(f, "No mapping could map the file to a matcher."),
-            FindCommandError::NoMatchFound => write!(f, "No matcher could match the file to a command."),
+            FindCommandError::NoMappingFound => {
+                write!(f, "No mapping could map the file to a matcher.")
+            }
+            FindCommandError::NoMatchFound => {
+                write!(f, "No matcher could match the file to a command.")
+            }
+        }
+    }
+}
+
+/// A RegEx pattern of a matcher that doesn't compile.
+#[derive(Debug)]
+pub struct PatternError {
+    pub matcher: String,
+    /// Where the pattern is within the matcher, e.g. "regexf", or
+    /// "any[1].regexc".
+    pub condition: String,
+    pub error: re::Error,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FassocRules {
+    /// Other rules files that these rules are based on, whose paths are
+    /// relative to this one. By the time the rules are deserialized, they
+    /// have already been merged into them.
+    pub include: Option<Vec<String>>,
+    pub options: Option<Options>,
+    // These are only optional so that an included rules file, or one that
+    // includes others, can leave out whatever it doesn't need.
+    #[serde(default)]
+    pub mappings: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub matchers: HashMap<String, Matcher>,
+    #[serde(default)]
+    pub commands: HashMap<String, Command>,
+    /// The name of the command to invoke when no other command could be
+    /// launched for the file.
+    pub on_error: Option<String>,
+}
+
+impl FassocRules {
+    /// Compiles the RegEx patterns of every matcher up front, so that invalid
+    /// patterns are reported when the rules are loaded, rather than when a
+    /// file they apply to is opened. Every invalid pattern is returned, to be
+    /// reported by the validator.
+    pub fn compile_patterns(&self) -> Result<(), Vec<PatternError>> {
+        let mut errors: Vec<PatternError> = Vec::new();
+
+        for (matcher_name, matcher) in &self.matchers {
+            let mut matcher_errors: Vec<(String, re::Error)> = Vec::new();
+            matcher.condition.compile_patterns("", &mut matcher_errors);
+
+            errors.extend(
+                matcher_errors
+                    .into_iter()
+                    .map(|(condition, error)| PatternError {
+                        matcher: matcher_name.to_owned(),
+                        condition,
+                        error,
+                    }),
+            );
+        }
+
+        errors.sort_by(|a, b| (&a.matcher, &a.condition).cmp(&(&b.matcher, &b.condition)));
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Starts a search for the commands that are suitable for the file. The
+    /// candidates of the mapping derived from the file extension are tried
+    /// first, followed by those of the mappings keyed by the MIME types of
+    /// the file, followed by those of the fallback mapping "*".
+    pub fn search_commands<'a>(&'a self, file_path: &'a Path) -> CommandSearch<'a> {
+        let file_name: Option<String> = file_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(String::from);
+
+        // File extension is allowed to be None, as it could stil be handled
+        // by the fallback catch-all mapping "*"
+        let file_ext_str = file_path
+            .extension()
+            .and_then(|ext| ext.to_str().map(String::from));
+
+        let extension_mapping = file_ext_str
+            .as_ref()
+            .and_then(|s| self.mappings.get_key_value(s));
+
+        CommandSearch {
+            rules: self,
+            file_path,
+            absolute_path: normalize_path(file_path),
+            file_name,
+            file_extension: file_ext_str,
+            mappings: extension_mapping.into_iter().collect(),
+            stage: MappingStage::Extension,
+            selected: false,
+            mapping_index: 0,
+            candidate_index: 0,
+            file_metadata: None,
+            file_head: (0, Vec::new()),
+            interpreter: None,
+            trace: MatchTrace::default(),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// CommandSearch
+// ----------------------------------------------------------------------------
+
+/// How many bytes from the start of a file are used to sniff its MIME type.
+const MIME_SNIFF_LENGTH: usize = 8192;
+
+/// The MIME types of scripts that the extension table of `mime_guess` either
+/// lacks, or considers to be plain text, which take priority over it.
+const SCRIPT_MIME_TYPES: [(&str, &str); 10] = [
+    ("py", "text/x-python"),
+    ("pyw", "text/x-python"),
+    ("rb", "text/x-ruby"),
+    ("go", "text/x-go"),
+    ("ts", "text/x-typescript"),
+    ("ps1", "text/x-powershell"),
+    ("sh", "text/x-shellscript"),
+    ("bash", "text/x-shellscript"),
+    ("bat", "text/x-msdos-batch"),
+    ("cmd", "text/x-msdos-batch"),
+];
+
+/// The kinds of mappings, in the order that they are consulted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MappingStage {
+    /// The mapping keyed by the extension of the file.
+    Extension,
+    /// The mappings keyed by the MIME types of the file, e.g. "image/png",
+    /// or "image/*".
+    Mime,
+    /// The fallback mapping "*".
+    Fallback,
+}
+
+/// A search through the candidates of the mappings that apply to a file,
+/// which can be resumed after a selected command fails to launch.
+pub struct CommandSearch<'a> {
+    rules: &'a FassocRules,
+    file_path: &'a Path,
+    /// The normalised absolute path of the file, for the path conditions.
+    absolute_path: PathBuf,
+    file_name: Option<String>,
+    file_extension: Option<String>,
+    /// The mappings that apply to the file so far, which grows as each stage
+    /// is reached, so that work such as sniffing the MIME type of the file is
+    /// only done if the previous mappings had no suitable candidate.
+    mappings: Vec<(&'a String, &'a Vec<String>)>,
+    stage: MappingStage,
+    /// Whether a command has been selected before, which can only be resumed
+    /// from because it failed to launch.
+    selected: bool,
+    mapping_index: usize,
+    candidate_index: usize,
+    /// File metadata is stored, so that it doesn't have to be read multiple
+    /// times. Reading is avoided unless needed, for performance reasons.
+    file_metadata: Option<FileMetadata>,
+    /// The first bytes of the file, read in binary for the file signatures
+    /// and content RegEx, along with how many were requested, as the file
+    /// may be shorter. More are read if a matcher needs more of them.
+    file_head: (usize, Vec<u8>),
+    /// The interpreter declared by the file, if it has been detected yet.
+    interpreter: Option<Option<Interpreter>>,
+    pub trace: MatchTrace,
+}
+
+impl<'a> CommandSearch<'a> {
+    /// Continues the search, returning the next command whose candidate
+    /// matches the file.
+    pub fn next_command(&mut self) -> Result<Selection<'a>, FindCommandError> {
+        let file_name_str: String = self
+            .file_name
+            .to_owned()
+            .ok_or(FindCommandError::CannotConvertPath)?;
+
+        loop {
+            while let Some(&(mapping_name, mapping)) = self.mappings.get(self.mapping_index) {
+                if self.candidate_index == 0 {
+                    self.trace.mappings.push(mapping_name.to_owned());
+                }
+
+                while let Some(matcher_name) = mapping.get(self.candidate_index) {
+                    let index = self.candidate_index;
+                    self.candidate_index += 1;
+
+                    if let Some(selection) =
+                        self.try_candidate(mapping_name, index, matcher_name, &file_name_str)
+                    {
+                        self.selected = true;
+                        return Ok(selection);
+                    }
+                }
+
+                self.mapping_index += 1;
+                self.candidate_index = 0;
+            }
+
+            if !self.advance_stage() {
+                break;
+            }
+        }
+
+        if self.trace.mappings.is_empty() {
+            // Neither the extension mapping, the MIME type mappings, nor the
+            // fallback mapping found.
+            Err(FindCommandError::NoMappingFound)
+        } else {
+            Err(FindCommandError::NoMatchFound)
+        }
+    }
+
+    /// Adds the mappings of the next stage, returning false if there are no
+    /// more stages.
+    fn advance_stage(&mut self) -> bool {
+        let rules: &'a FassocRules = self.rules;
+
+        let added: Vec<(&'a String, &'a Vec<String>)> = match self.stage {
+            MappingStage::Extension => {
+                self.stage = MappingStage::Mime;
+
+                // Sniffing is avoided entirely if no mapping is a MIME type.
+                if rules.mappings.keys().any(|key| key.contains('/')) {
+                    self.mime_types()
+                        .iter()
+                        .flat_map(|mime| {
+                            let wildcard =
+                                mime.split_once('/').map(|(kind, _)| format!("{}/*", kind));
+                            [Some(mime.to_owned()), wildcard]
+                        })
+                        .flatten()
+                        .filter_map(|key| rules.mappings.get_key_value(&key))
+                        .collect()
+                } else {
+                    Vec::new()
+                }
+            }
+
+            MappingStage::Mime => {
+                self.stage = MappingStage::Fallback;
+
+                // The fallback mapping is only for files that no other mapping
+                // applies to, unless a command was selected but failed to launch.
+                if !self.mappings.is_empty() && !self.selected {
+                    return false;
+                }
+
+                rules.mappings.get_key_value("*").into_iter().collect()
+            }
+
+            MappingStage::Fallback => return false,
+        };
+
+        for mapping in added {
+            if !self.mappings.iter().any(|(name, _)| *name == mapping.0) {
+                self.mappings.push(mapping);
+            }
+        }
+
+        true
+    }
+
+    /// The MIME types of the file, from the most to the least reliable, which
+    /// are the type sniffed from a binary signature, followed by the types
+    /// associated with the file extension, followed by "text/plain" if the
+    /// first bytes of the file appear to be text.
+    fn mime_types(&mut self) -> Vec<String> {
+        let mut mime_types: Vec<String> = Vec::new();
+        let head: Vec<u8> = self.ensure_head_read(MIME_SNIFF_LENGTH).to_vec();
+
+        if let Some(sniffed) = infer::get(&head) {
+            mime_types.push(sniffed.mime_type().to_owned());
+        }
+
+        if let Some(extension) = &self.file_extension {
+            mime_types.extend(
+                SCRIPT_MIME_TYPES
+                    .iter()
+                    .filter(|(script, _)| script.eq_ignore_ascii_case(extension))
+                    .map(|(_, mime)| String::from(*mime)),
+            );
+
+            mime_types.extend(
+                mime_guess::from_ext(extension)
+                    .iter()
+                    .map(|mime| mime.essence_str().to_owned()),
+            );
+        }
+
+        let is_text = !head.contains(&0)
+            && match std::str::from_utf8(&head) {
+                Ok(_) => true,
+                // The head may have been cut off in the middle of a character.
+                Err(error) => error.error_len().is_none(),
+            };
+
+        if mime_types.is_empty() && is_text {
+            mime_types.push(String::from("text/plain"));
+        }
+
+        mime_types.dedup();
+        log::debug!("Determined the MIME types of the file: {:?}", mime_types);
+        mime_types
+    }
+
+    fn ensure_metadata_read(&mut self) -> &FileMetadata {
+        let file_path = self.file_path;
+        self.file_metadata
+            .get_or_insert_with(|| FileMetadata::read(file_path))
+    }
+
+    /// Reads up to `length` bytes from the start of the file, unless at least
+    /// as many have been read already. Fewer bytes are returned if the file
+    /// is shorter, or cannot be read.
+    fn ensure_head_read(&mut self, length: usize) -> &[u8] {
+        let file_path = self.file_path;
+
+        if self.file_head.0 < length {
+            // The buffer grows as the file is read, rather than reserving the
+            // whole length up front, as the length may be far larger than the
+            // file, e.g. a content_limit of "64GB".
+            let mut head: Vec<u8> = Vec::new();
+
+            std::fs::File::open(file_path)
+                .and_then(|file| file.take(length as u64).read_to_end(&mut head))
+                .map_err(|e| {
+                    log::error!(
+                        "Failed to read the contents of file \"{}\" because: {}",
+                        file_path.to_str().unwrap_or("CANNOT_GET_FILE"),
+                        e
+                    )
+                })
+                .ok();
+
+            self.file_head = (length, head);
+        }
+
+        let head: &[u8] = &self.file_head.1;
+        &head[..length.min(head.len())]
+    }
+
+    /// Detects the interpreter declared by the shebang or modeline of the
+    /// file, which only requires its first and last few bytes.
+    fn ensure_interpreter_detected(&mut self) -> Option<&Interpreter> {
+        if self.interpreter.is_none() {
+            let head: Vec<u8> = self
+                .ensure_head_read(interpreter::DETECTION_LENGTH)
+                .to_vec();
+
+            let tail: Vec<u8> = if head.len() < interpreter::DETECTION_LENGTH {
+                head.to_owned()
+            } else {
+                read_tail(self.file_path, interpreter::DETECTION_LENGTH)
+            };
+
+            let detected = Interpreter::detect(&head, &tail);
+            log::debug!("Detected the interpreter of the file: {:?}", detected);
+            self.interpreter = Some(detected);
+        }
+
+        self.interpreter.as_ref().and_then(Option::as_ref)
+    }
+
+    /// Evaluates the conditions, recording each of them, until one of them
+    /// fails. Returns whether all of them passed.
+    fn evaluate_condition(
+        &mut self,
+        matcher: &Matcher,
+        condition: &Condition,
+        file_name_str: &str,
+        conditions: &mut Vec<ConditionTrace>,
+    ) -> bool {
+        let rules: &'a FassocRules = self.rules;
+        let passing = |conditions: &Vec<ConditionTrace>| conditions.iter().all(|c| c.passed);
+
+        let absolute_path: PathBuf = self.absolute_path.to_owned();
+        let file_path: &Path = &absolute_path;
+        let file_dir: &Path = file_path.parent().unwrap_or(file_path);
+        let undefined: UndefinedVariables = rules
+            .options
+            .as_ref()
+            .and_then(|o| o.undefined_variables)
+            .unwrap_or_default();
+
+        // Conditions that only concern the path of the file, in the order they
+        // are evaluated. Matching any one of several globs is enough.
+        let path_conditions: [(&'static str, Option<String>, ConditionCheck); 7] = [
+            (
+                "regexf",
+                condition.regexf.as_ref().map(Pattern::to_string),
+                &|| Matcher::rmatch_file(condition.regexf.as_ref(), file_name_str),
+            ),
+            (
+                "glob",
+                condition.glob.as_ref().map(Globs::to_string),
+                &|| matcher.gmatch(condition.glob.as_ref(), file_name_str),
+            ),
+            (
+                "regexp",
+                condition.regexp.as_ref().map(Pattern::to_string),
+                &|| Matcher::rmatch_path(condition.regexp.as_ref(), file_path),
+            ),
+            (
+                "regexd",
+                condition.regexd.as_ref().map(Pattern::to_string),
+                &|| Matcher::rmatch_path(condition.regexd.as_ref(), file_dir),
+            ),
+            (
+                "globp",
+                condition.globp.as_ref().map(Globs::to_string),
+                &|| matcher.gmatch(condition.globp.as_ref(), file_path),
+            ),
+            (
+                "globd",
+                condition.globd.as_ref().map(Globs::to_string),
+                &|| matcher.gmatch(condition.globd.as_ref(), file_dir),
+            ),
+            ("under", condition.under.to_owned(), &|| {
+                Matcher::is_under(
+                    condition.under.as_deref().unwrap_or_default(),
+                    undefined,
+                    file_path,
+                )
+            }),
+        ];
+
+        for (name, pattern, check) in path_conditions {
+            if let Some(pattern) = pattern.filter(|_| passing(conditions)) {
+                conditions.push(ConditionTrace::evaluate(name, &pattern, check()));
+            }
+        }
+
+        // Conditions concerning the metadata of the file, which is only read
+        // if there are any, and always before the content is read.
+        if condition.has_metadata_conditions() && passing(conditions) {
+            let metadata: &FileMetadata = self.ensure_metadata_read();
+
+            let metadata_conditions: [(&'static str, Option<String>, ConditionCheck); 10] = [
+                (
+                    "min_size",
+                    condition.min_size.as_ref().map(Amount::to_string),
+                    &|| metadata.check_size(condition.min_size.as_ref(), |size, min| size >= min),
+                ),
+                (
+                    "max_size",
+                    condition.max_size.as_ref().map(Amount::to_string),
+                    &|| metadata.check_size(condition.max_size.as_ref(), |size, max| size <= max),
+                ),
+                (
+                    "min_modified_age",
+                    condition.min_modified_age.as_ref().map(Amount::to_string),
+                    &|| {
+                        metadata.check_age(
+                            condition.min_modified_age.as_ref(),
+                            Metadata::modified,
+                            |age, min| age >= min,
+                        )
+                    },
+                ),
+                (
+                    "max_modified_age",
+                    condition.max_modified_age.as_ref().map(Amount::to_string),
+                    &|| {
+                        metadata.check_age(
+                            condition.max_modified_age.as_ref(),
+                            Metadata::modified,
+                            |age, max| age <= max,
+                        )
+                    },
+                ),
+                (
+                    "min_created_age",
+                    condition.min_created_age.as_ref().map(Amount::to_string),
+                    &|| {
+                        metadata.check_age(
+                            condition.min_created_age.as_ref(),
+                            Metadata::created,
+                            |age, min| age >= min,
+                        )
+                    },
+                ),
+                (
+                    "max_created_age",
+                    condition.max_created_age.as_ref().map(Amount::to_string),
+                    &|| {
+                        metadata.check_age(
+                            condition.max_created_age.as_ref(),
+                            Metadata::created,
+                            |age, max| age <= max,
+                        )
+                    },
+                ),
+                ("empty", condition.empty.map(|b| b.to_string()), &|| {
+                    Ok(metadata
+                        .check_flag(condition.empty, |m| Some(m.target.as_ref()?.len() == 0)))
+                }),
+                (
+                    "readonly",
+                    condition.readonly.map(|b| b.to_string()),
+                    &|| {
+                        Ok(metadata.check_flag(condition.readonly, |m| {
+                            Some(m.target.as_ref()?.permissions().readonly())
+                        }))
+                    },
+                ),
+                ("hidden", condition.hidden.map(|b| b.to_string()), &|| {
+                    Ok(metadata.check_flag(condition.hidden, |m| Some(m.hidden)))
+                }),
+                ("symlink", condition.symlink.map(|b| b.to_string()), &|| {
+                    Ok(metadata.check_flag(condition.symlink, |m| {
+                        Some(m.link.as_ref()?.file_type().is_symlink())
+                    }))
+                }),
+            ];
+
+            for (name, pattern, check) in metadata_conditions {
+                if let Some(pattern) = pattern.filter(|_| passing(conditions)) {
+                    conditions.push(ConditionTrace::evaluate(name, &pattern, check()));
+                }
+            }
+        }
+
+        if let Some(project) = condition.project.as_ref().filter(|_| passing(conditions)) {
+            conditions.push(ConditionTrace::evaluate(
+                "project",
+                &project.to_string(),
+                project.find_root(file_dir),
+            ));
+        }
+
+        // If there are file signatures, check them against the first bytes of
+        // the file, reading no more than the signatures need.
+        if let Some(magic) = condition.magic.as_ref().filter(|_| passing(conditions)) {
+            let result = Matcher::magic_patterns(magic).map(|patterns| {
+                let length = patterns
+                    .iter()
+                    .filter_map(Matcher::signature_end)
+                    .max()
+                    .unwrap_or(0);
+
+                let head: &[u8] = self.ensure_head_read(length);
+                patterns
+                    .iter()
+                    .any(|pattern| Matcher::match_signature(pattern, head))
+                    .then(Captures::new)
+            });
+
+            conditions.push(ConditionTrace::evaluate(
+                "magic",
+                &magic.to_string(),
+                result,
+            ));
+        }
+
+        // If there are interpreter globs, match them against the name of the
+        // interpreter declared by the shebang or modeline of the file.
+        if let Some(globs) = condition
+            .interpreter
+            .as_ref()
+            .filter(|_| passing(conditions))
+        {
+            let result = match self.ensure_interpreter_detected().cloned() {
+                Some(detected) => matcher.gmatch(Some(globs), &detected.name).map(|matched| {
+                    matched.map(|_| {
+                        Captures::from([
+                            (String::from("name"), detected.name),
+                            (String::from("path"), detected.path),
+                            (String::from("source"), detected.source.to_string()),
+                        ])
+                    })
+                }),
+                None => Ok(None),
+            };
+
+            conditions.push(ConditionTrace::evaluate(
+                "interpreter",
+                &globs.to_string(),
+                result,
+            ));
+        }
+
+        // If there is a file content RegEx, match the RegEx against the file,
+        // unless the match has already failed. Only the first bytes of the
+        // file are matched against, unless the whole file is streamed.
+        if let Some(regexc) = condition.regexc.as_ref().filter(|_| passing(conditions)) {
+            let global_limit = rules
+                .options
+                .as_ref()
+                .and_then(|o| o.content_limit.as_ref());
+
+            let result = if matcher.content_stream.unwrap_or(false) {
+                Matcher::rmatch_file_stream(regexc, self.file_path)
+            } else {
+                let limit = matcher
+                    .content_limit
+                    .as_ref()
+                    .or(global_limit)
+                    .map(|limit| limit.value(&SIZE_UNITS))
+                    .transpose();
+
+                limit.and_then(|limit| {
+                    let limit = limit.unwrap_or(DEFAULT_CONTENT_LIMIT) as usize;
+                    Matcher::rmatch_file_content(regexc, self.ensure_head_read(limit))
+                })
+            };
+
+            conditions.push(ConditionTrace::evaluate("regexc", regexc.source(), result));
+        }
+
+        // The groups are evaluated lazily as well, where "all" stops at its
+        // first failing member, and "any" at its first passing member.
+        if let Some(members) = condition.all.as_ref().filter(|_| passing(conditions)) {
+            let mut children: Vec<ConditionTrace> = Vec::new();
+
+            for member in members {
+                let mut member_conditions: Vec<ConditionTrace> = Vec::new();
+                let passed =
+                    self.evaluate_condition(matcher, member, file_name_str, &mut member_conditions);
+                children.push(ConditionTrace::group("member", passed, member_conditions));
+
+                if !passed {
+                    break;
+                }
+            }
+
+            let passed = passing(&children);
+            conditions.push(ConditionTrace::group("all", passed, children));
+        }
+
+        if let Some(members) = condition.any.as_ref().filter(|_| passing(conditions)) {
+            let mut children: Vec<ConditionTrace> = Vec::new();
+
+            for member in members {
+                let mut member_conditions: Vec<ConditionTrace> = Vec::new();
+                let passed =
+                    self.evaluate_condition(matcher, member, file_name_str, &mut member_conditions);
+                children.push(ConditionTrace::group("member", passed, member_conditions));
+
+                if passed {
+                    break;
+                }
+            }
+
+            let passed = children.iter().any(|c| c.passed);
+            conditions.push(ConditionTrace::group("any", passed, children));
+        }
+
+        if let Some(negated) = condition.not.as_ref().filter(|_| passing(conditions)) {
+            let mut children: Vec<ConditionTrace> = Vec::new();
+            let passed = !self.evaluate_condition(matcher, negated, file_name_str, &mut children);
+            conditions.push(ConditionTrace::group("not", passed, children));
+        }
+
+        passing(conditions)
+    }
+
+    fn try_candidate(
+        &mut self,
+        mapping_name: &str,
+        index: usize,
+        matcher_name: &str,
+        file_name_str: &str,
+    ) -> Option<Selection<'a>> {
+        let rules: &'a FassocRules = self.rules;
+
+        log::debug!("Trying matcher #{} - {}", index, matcher_name);
+
+        let matcher: &Matcher = match rules.matchers.get(matcher_name) {
+            Some(matcher) => matcher,
+            None => match rules.commands.get_key_value(matcher_name) {
+                Some((command_name, command)) => {
+                    log::debug!(
+                            "Mapping \"{}\" referred to \"{}\" which isn't a valid matcher, but it is a valid command, mapping directly to command instead.",
+                            mapping_name,
+                            matcher_name
+                        );
+
+                    self.trace.push(
+                        mapping_name,
+                        matcher_name,
+                        CandidateOutcome::DirectCommand,
+                        Vec::new(),
+                    );
+
+                    return Some(Selection {
+                        name: command_name,
+                        command,
+                        captures: Captures::new(),
+                    });
+                }
+
+                None => {
+                    log::warn!(
+                            "Ignored a matcher with mame \"{}\" from mapping \"{}\" because it doesn't point to anything that exists.",
+                            matcher_name,
+                            mapping_name
+                        );
+
+                    self.trace.push(
+                        mapping_name,
+                        matcher_name,
+                        CandidateOutcome::Dangling,
+                        Vec::new(),
+                    );
+
+                    return None;
+                }
+            },
+        };
+
+        let (command_name, matcher_command) = match rules.commands.get_key_value(&matcher.command) {
+            Some(command) => command,
+            None => {
+                log::warn!("The command pointed to by matcher \"{}\" does not exist, ignoring this matcher.", matcher_name);
+                self.trace.push(
+                    mapping_name,
+                    matcher_name,
+                    CandidateOutcome::MissingCommand(matcher.command.to_owned()),
+                    Vec::new(),
+                );
+                return None;
+            }
+        };
+
+        let mut conditions: Vec<ConditionTrace> = Vec::new();
+        let passed: bool =
+            self.evaluate_condition(matcher, &matcher.condition, file_name_str, &mut conditions);
+
+        // If the matcher is still valid after validation, return it.
+        if passed {
+            log::debug!("Matcher #{} - {} - matched this file.", index, matcher_name);
+
+            let mut captures: Captures = Captures::new();
+
+            for condition in &conditions {
+                condition.collect_captures(&mut captures);
+            }
+
+            self.trace.push(
+                mapping_name,
+                matcher_name,
+                CandidateOutcome::Matched,
+                conditions,
+            );
+
+            return Some(Selection {
+                name: command_name,
+                command: matcher_command,
+                captures,
+            });
+        }
+
+        self.trace.push(
+            mapping_name,
+            matcher_name,
+            CandidateOutcome::Failed,
+            conditions,
+        );
+        None
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Options
+// ----------------------------------------------------------------------------
+
+/// Settings that apply to the rules file as a whole.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Options {
+    /// What to do with environment variables in rule strings that aren't
+    /// defined, either "error", "empty", or "literal" (the default).
+    pub undefined_variables: Option<UndefinedVariables>,
+    /// How deeply instances of fassoc-proxy may be nested, i.e. launched by
+    /// one another, before refusing to launch anything, defaults to 3.
+    pub max_depth: Option<usize>,
+    /// How many bytes from the start of a file are matched against content
+    /// RegEx, unless a matcher specifies otherwise, defaults to 1 MiB.
+    pub content_limit: Option<Amount>,
+}
+
+/// How many bytes from the start of a file are matched against content RegEx
+/// by default.
+const DEFAULT_CONTENT_LIMIT: u64 = 1 << 20;
+
+// ----------------------------------------------------------------------------
+// MatchTrace
+// ----------------------------------------------------------------------------
+
+/// A command chosen by a `CommandSearch`.
+#[derive(Debug)]
+pub struct Selection<'a> {
+    pub name: &'a str,
+    pub command: &'a Command,
+    /// The groups captured by the conditions of the matcher that selected the
+    /// command, prefixed by the condition, e.g. "regexf.line".
+    pub captures: Captures,
+}
+
+/// A record of every step a `CommandSearch` took in order to arrive at its
+/// selections, used to explain why a command was chosen.
+#[derive(Debug, Default)]
+pub struct MatchTrace {
+    /// The names of the mappings that were used, in order, which are any of
+    /// the extension of the file, the MIME types of the file, e.g. "image/png"
+    /// or "image/*", and the fallback mapping "*".
+    pub mappings: Vec<String>,
+    pub candidates: Vec<CandidateTrace>,
+}
+
+impl MatchTrace {
+    fn push(
+        &mut self,
+        mapping: &str,
+        name: &str,
+        outcome: CandidateOutcome,
+        conditions: Vec<ConditionTrace>,
+    ) {
+        self.candidates.push(CandidateTrace {
+            mapping: mapping.to_owned(),
+            name: name.to_owned(),
+            outcome,
+            conditions,
+        });
+    }
+}
+
+/// A single entry of a mapping list, and what became of it.
+#[derive(Debug)]
+pub struct CandidateTrace {
+    /// The name of the mapping the entry belongs to.
+    pub mapping: String,
+    pub name: String,
+    pub outcome: CandidateOutcome,
+    pub conditions: Vec<ConditionTrace>,
+}
+
+#[derive(Debug)]
+pub enum CandidateOutcome {
+    /// Every condition of the matcher passed.
+    Matched,
+    /// At least one condition of the matcher failed.
+    Failed,
+    /// The name referred to a command rather than a matcher.
+    DirectCommand,
+    /// The matcher pointed to a command that does not exist.
+    MissingCommand(String),
+    /// The name referred to neither a matcher nor a command.
+    Dangling,
+}
+
+impl std::fmt::Display for CandidateOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CandidateOutcome::Matched => write!(f, "matched"),
+            CandidateOutcome::Failed => write!(f, "did not match"),
+            CandidateOutcome::DirectCommand => write!(f, "is a command, selected directly"),
+            CandidateOutcome::MissingCommand(command) => {
+                write!(f, "ignored, points to the missing command \"{}\"", command)
+            }
+            CandidateOutcome::Dangling => write!(f, "ignored, is neither a matcher nor a command"),
+        }
+    }
+}
+
+/// The evaluation of a single matcher condition, such as "regexf".
+#[derive(Debug)]
+pub struct ConditionTrace {
+    pub condition: &'static str,
+    pub pattern: String,
+    pub passed: bool,
+    pub error: Option<String>,
+    /// The groups captured by the condition, prefixed by the condition name.
+    pub captures: Captures,
+    /// The conditions within a group, e.g. "all", which are empty for any
+    /// other condition.
+    pub children: Vec<ConditionTrace>,
+}
+
+impl ConditionTrace {
+    fn evaluate(
+        condition: &'static str,
+        pattern: &str,
+        result: Result<Option<Captures>, MatcherError>,
+    ) -> ConditionTrace {
+        let (captures, error) = match result {
+            Ok(captures) => (captures, None),
+            Err(error) => {
+                log::error!(
+                    "Encountered an error when evaluating the condition \"{}\": {}",
+                    condition,
+                    error
+                );
+                (None, Some(error.to_string()))
+            }
+        };
+
+        ConditionTrace {
+            condition,
+            pattern: pattern.to_owned(),
+            passed: captures.is_some(),
+            error,
+            captures: captures
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(group, value)| (format!("{}.{}", condition, group), value))
+                .collect(),
+            children: Vec::new(),
+        }
+    }
+
+    fn group(
+        condition: &'static str,
+        passed: bool,
+        children: Vec<ConditionTrace>,
+    ) -> ConditionTrace {
+        ConditionTrace {
+            condition,
+            pattern: String::new(),
+            passed,
+            error: None,
+            captures: Captures::new(),
+            children,
+        }
+    }
+
+    /// Collects the captures of the condition, if it passed, along with those
+    /// of its children that passed. The captures within a "not" group are
+    /// never collected, as the group only passes if its conditions didn't.
+    pub fn collect_captures(&self, captures: &mut Captures) {
+        if !self.passed {
+            return;
+        }
+
+        captures.extend(self.captures.to_owned());
+
+        if self.condition != "not" {
+            for child in &self.children {
+                child.collect_captures(captures);
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Matcher
+// ----------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum MatcherError {
+    RegexCompileError(re::Error),
+    InvalidGlob(gs::Error),
+    InvalidDirectory(TemplateError),
+    InvalidAmount(String),
+    InvalidSignature(String),
+    InvalidOffset(u64),
+    MetadataUnavailable(String),
+    NoRegexError,
+}
+
+impl std::fmt::Display for MatcherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatcherError::RegexCompileError(e) => write!(f, "RegEx Compile Error: {}", e),
+            MatcherError::InvalidGlob(e) => write!(f, "Glob Compile Error: {}", e),
+            MatcherError::InvalidDirectory(e) => write!(f, "Invalid Directory: {}", e),
+            MatcherError::InvalidAmount(amount) => write!(
+                f,
+                "Invalid Amount: \"{}\" is not a number with a known unit",
+                amount
+            ),
+            MatcherError::InvalidSignature(signature) => {
+                write!(
+                    f,
+                    "Invalid Signature: \"{}\" is neither a known name, nor hexadecimal bytes",
+                    signature
+                )
+            }
+            MatcherError::InvalidOffset(offset) => write!(
+                f,
+                "Invalid Offset: {} is beyond the maximum signature offset of {}",
+                offset, MAX_SIGNATURE_OFFSET
+            ),
+            MatcherError::MetadataUnavailable(e) => write!(f, "Metadata Unavailable: {}", e),
+            MatcherError::NoRegexError => write!(f, "The matcher has no RegEx to match with."),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Matcher {
+    pub command: String,
+    /// Whether the globs of the matcher are case sensitive. Defaults to false
+    /// on Windows, and true elsewhere, following the file system.
+    pub case_sensitive: Option<bool>,
+    /// How many bytes from the start of the file `regexc` is matched against,
+    /// overriding the global `content_limit` option.
+    pub content_limit: Option<Amount>,
+    /// Match `regexc` against the whole file, by searching through it in
+    /// chunks, rather than holding all of it in memory.
+    pub content_stream: Option<bool>,
+    /// The conditions the file must satisfy, which are written directly in
+    /// the matcher.
+    #[serde(flatten)]
+    pub condition: Condition,
+}
+
+/// A set of conditions that are AND-ed together, where each condition that
+/// isn't present is ignored. Conditions are evaluated in the order of their
+/// cost, where the content of the file is only read once a condition that
+/// needs it is reached, and the groups `all`, `any` and `not` come last.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Condition {
+    /// A RegEx pattern that the name of the file must match.
+    pub regexf: Option<Pattern<re::Regex>>,
+    /// A glob, or a list of globs, at least one of which the file name must
+    /// match, e.g. "*.test.ts".
+    pub glob: Option<Globs>,
+    /// A RegEx pattern that the absolute path of the file must match.
+    pub regexp: Option<Pattern<re::Regex>>,
+    /// A RegEx pattern that the absolute path of the directory containing the
+    /// file must match.
+    pub regexd: Option<Pattern<re::Regex>>,
+    /// Globs matched against the absolute path of the file.
+    pub globp: Option<Globs>,
+    /// Globs matched against the absolute path of the directory containing
+    /// the file.
+    pub globd: Option<Globs>,
+    /// A directory that the file must be located in, directly or indirectly.
+    pub under: Option<String>,
+    /// Marker files or directories, at least one of which must be present in
+    /// the directory of the file, or one of its ancestors.
+    pub project: Option<Project>,
+    /// The minimum size of the file, either a number of bytes, or a string
+    /// with a unit, e.g. "50MB".
+    pub min_size: Option<Amount>,
+    /// The maximum size of the file, in the same form as `min_size`.
+    pub max_size: Option<Amount>,
+    /// The minimum time since the file was modified, either a number of
+    /// seconds, or a string with a unit, e.g. "7d".
+    pub min_modified_age: Option<Amount>,
+    /// The maximum time since the file was modified.
+    pub max_modified_age: Option<Amount>,
+    /// The minimum time since the file was created.
+    pub min_created_age: Option<Amount>,
+    /// The maximum time since the file was created.
+    pub max_created_age: Option<Amount>,
+    /// Whether the file must be empty, or must not be.
+    pub empty: Option<bool>,
+    /// Whether the file must be read-only, or must not be.
+    pub readonly: Option<bool>,
+    /// Whether the file must be hidden, or must not be.
+    pub hidden: Option<bool>,
+    /// Whether the path must be a symbolic link, or must not be.
+    pub symlink: Option<bool>,
+    /// A file signature, or a list of file signatures, at least one of which
+    /// the file must start with.
+    pub magic: Option<OneOrMany<Signature>>,
+    /// Globs matched against the name of the interpreter declared by the
+    /// shebang of the file, e.g. "python3", or the language declared by a
+    /// Vim or Emacs modeline, e.g. "python".
+    pub interpreter: Option<Globs>,
+    /// A RegEx pattern that the content of the file must match.
+    pub regexc: Option<Pattern<re::bytes::Regex>>,
+    /// Groups of conditions that must all pass.
+    pub all: Option<Vec<Condition>>,
+    /// Groups of conditions, at least one of which must pass.
+    pub any: Option<Vec<Condition>>,
+    /// A group of conditions that must not pass.
+    pub not: Option<Box<Condition>>,
+}
+
+impl Condition {
+    /// Compiles every RegEx pattern of the conditions, including those within
+    /// groups, collecting an error for each pattern that is invalid, where
+    /// `location` is the path of the condition within the matcher.
+    fn compile_patterns(&self, location: &str, errors: &mut Vec<(String, re::Error)>) {
+        let at = |name: &str| {
+            if location.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{}.{}", location, name)
+            }
+        };
+
+        let patterns = [
+            ("regexf", self.regexf.as_ref().map(|p| p.compile().err())),
+            ("regexp", self.regexp.as_ref().map(|p| p.compile().err())),
+            ("regexd", self.regexd.as_ref().map(|p| p.compile().err())),
+            ("regexc", self.regexc.as_ref().map(|p| p.compile().err())),
+        ];
+
+        for (name, error) in patterns {
+            if let Some(Some(error)) = error {
+                errors.push((at(name), error));
+            }
+        }
+
+        for (group, members) in [("all", &self.all), ("any", &self.any)] {
+            for (index, member) in members.iter().flatten().enumerate() {
+                member.compile_patterns(&format!("{}[{}]", at(group), index), errors);
+            }
+        }
+
+        if let Some(negated) = &self.not {
+            negated.compile_patterns(&at("not"), errors);
+        }
+    }
+
+    fn has_metadata_conditions(&self) -> bool {
+        [
+            &self.min_size,
+            &self.max_size,
+            &self.min_modified_age,
+            &self.max_modified_age,
+            &self.min_created_age,
+            &self.max_created_age,
+        ]
+        .iter()
+        .any(|amount| amount.is_some())
+            || [self.empty, self.readonly, self.hidden, self.symlink]
+                .iter()
+                .any(Option::is_some)
+    }
+}
+
+/// A RegEx pattern in the rules file, along with the RegEx it compiles to. The
+/// RegEx is compiled once, either when the rules are loaded, or when it is
+/// first needed, and then reused for every file it is matched against.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "String", into = "String", bound = "")]
+pub struct Pattern<R: CompileRegex> {
+    source: String,
+    compiled: OnceLock<Result<R, re::Error>>,
+}
+
+/// The kinds of RegEx a `Pattern` can compile to, which are the RegEx for
+/// text, and the RegEx for bytes used for the content of files.
+pub trait CompileRegex: Clone + Sized {
+    fn compile(source: &str) -> Result<Self, re::Error>;
+}
+
+impl CompileRegex for re::Regex {
+    fn compile(source: &str) -> Result<Self, re::Error> {
+        re::Regex::new(source)
+    }
+}
+
+impl CompileRegex for re::bytes::Regex {
+    fn compile(source: &str) -> Result<Self, re::Error> {
+        re::bytes::Regex::new(source)
+    }
+}
+
+impl<R: CompileRegex> Pattern<R> {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Compiles the pattern, unless it has been compiled already.
+    pub fn compile(&self) -> Result<&R, re::Error> {
+        self.compiled
+            .get_or_init(|| R::compile(&self.source))
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    fn regex(&self) -> Result<&R, MatcherError> {
+        self.compile().map_err(MatcherError::RegexCompileError)
+    }
+}
+
+impl<R: CompileRegex> From<String> for Pattern<R> {
+    fn from(source: String) -> Self {
+        Pattern {
+            source,
+            compiled: OnceLock::new(),
+        }
+    }
+}
+
+impl<R: CompileRegex> From<Pattern<R>> for String {
+    fn from(pattern: Pattern<R>) -> Self {
+        pattern.source
+    }
+}
+
+impl<R: CompileRegex> std::fmt::Display for Pattern<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Either a single value, or a list of values, such as globs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn items(&self) -> &[T] {
+        match self {
+            OneOrMany::One(item) => std::slice::from_ref(item),
+            OneOrMany::Many(items) => items,
+        }
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for OneOrMany<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, item) in self.items().iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+
+            write!(f, "{}", item)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub type Globs = OneOrMany<String>;
+
+/// A file signature, which is either the name of a built-in signature, e.g.
+/// "png", a string of hexadecimal bytes that the file must start with, e.g.
+/// "50 4B 03 04", or bytes at a specific offset. Any byte can be written as
+/// "??" in order to match any value.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Signature {
+    Text(String),
+    Bytes { bytes: String, offset: Option<u64> },
+}
+
+/// The signatures that can be referred to by name, where a name can occur
+/// multiple times if there are several variations of the signature.
+const BUILTIN_SIGNATURES: [(&str, u64, &str); 9] = [
+    ("png", 0, "89 50 4E 47 0D 0A 1A 0A"),
+    ("pdf", 0, "25 50 44 46 2D"),
+    ("zip", 0, "50 4B 03 04"),
+    ("zip", 0, "50 4B 05 06"),
+    ("zip", 0, "50 4B 07 08"),
+    ("elf", 0, "7F 45 4C 46"),
+    ("pe", 0, "4D 5A"),
+    ("gzip", 0, "1F 8B"),
+    (
+        "sqlite",
+        0,
+        "53 51 4C 69 74 65 20 66 6F 72 6D 61 74 20 33 00",
+    ),
+];
+
+/// The largest offset a signature may have, since the file is read up to the
+/// end of the signature. Real signatures are rarely further in than a few
+/// dozen kilobytes, e.g. ISO 9660 images at 32769.
+pub const MAX_SIGNATURE_OFFSET: u64 = 1 << 20;
+
+/// A sequence of bytes at an offset, where `None` matches any byte.
+type BytePattern = (u64, Vec<Option<u8>>);
+
+impl Signature {
+    pub fn patterns(&self) -> Result<Vec<BytePattern>, MatcherError> {
+        let (text, offset) = match self {
+            Signature::Text(text) => (text, 0),
+            Signature::Bytes { bytes, offset } => (bytes, offset.unwrap_or(0)),
+        };
+
+        if let Signature::Text(name) = self {
+            let builtin: Vec<&(&str, u64, &str)> = BUILTIN_SIGNATURES
+                .iter()
+                .filter(|(builtin, _, _)| builtin.eq_ignore_ascii_case(name))
+                .collect();
+
+            if !builtin.is_empty() {
+                return builtin
+                    .into_iter()
+                    .map(|(_, offset, bytes)| Ok((*offset, Signature::parse_hex(bytes)?)))
+                    .collect();
+            }
+        }
+
+        if offset > MAX_SIGNATURE_OFFSET {
+            return Err(MatcherError::InvalidOffset(offset));
+        }
+
+        Ok(vec![(offset, Signature::parse_hex(text)?)])
+    }
+
+    fn parse_hex(text: &str) -> Result<Vec<Option<u8>>, MatcherError> {
+        let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return Err(MatcherError::InvalidSignature(text.to_owned()));
+        }
+
+        digits
+            .chunks(2)
+            .map(|pair| match pair {
+                ['?', '?'] => Ok(None),
+                [high, low] => match (high.to_digit(16), low.to_digit(16)) {
+                    (Some(high), Some(low)) => Ok(Some((high * 16 + low) as u8)),
+                    _ => Err(MatcherError::InvalidSignature(text.to_owned())),
+                },
+                _ => Err(MatcherError::InvalidSignature(text.to_owned())),
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Signature::Text(text) => write!(f, "{}", text),
+            Signature::Bytes { bytes, offset } => write!(f, "{} at {}", bytes, offset.unwrap_or(0)),
+        }
+    }
+}
+
+/// A condition that identifies the project a file belongs to, by walking up
+/// from the file until a directory containing one of the markers is found.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Project {
+    /// The names of the files or directories that mark the root of a project,
+    /// e.g. "Cargo.toml", ".git" or "package.json".
+    pub markers: Vec<String>,
+    /// How many ancestors of the directory containing the file are searched,
+    /// where 0 only searches the directory itself. Unlimited by default.
+    pub max_depth: Option<usize>,
+}
+
+impl Project {
+    /// Finds the nearest directory, starting at `directory`, that contains one
+    /// of the markers. The directory and the marker found in it are captured
+    /// as "root" and "marker" respectively.
+    pub fn find_root(&self, directory: &Path) -> Result<Option<Captures>, MatcherError> {
+        let ancestors = directory.ancestors().take(
+            self.max_depth
+                .map_or(usize::MAX, |depth| depth.saturating_add(1)),
+        );
+
+        for ancestor in ancestors {
+            let marker = self
+                .markers
+                .iter()
+                .find(|marker| ancestor.join(marker).symlink_metadata().is_ok());
+
+            if let Some(marker) = marker {
+                return Ok(Some(Captures::from([
+                    (
+                        String::from("root"),
+                        ancestor.to_string_lossy().into_owned(),
+                    ),
+                    (String::from("marker"), marker.to_owned()),
+                ])));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl std::fmt::Display for Project {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.markers.join(", "))?;
+
+        if let Some(max_depth) = self.max_depth {
+            write!(f, " (max depth {})", max_depth)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A quantity in a matcher condition, either a plain number, or a string
+/// consisting of a number and a unit, e.g. "50MB" or "7d".
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Amount {
+    Number(u64),
+    Text(String),
+}
+
+pub const SIZE_UNITS: [(&str, u64); 9] = [
+    ("B", 1),
+    ("KB", 1000),
+    ("MB", 1000 * 1000),
+    ("GB", 1000 * 1000 * 1000),
+    ("TB", 1000 * 1000 * 1000 * 1000),
+    ("KiB", 1 << 10),
+    ("MiB", 1 << 20),
+    ("GiB", 1 << 30),
+    ("TiB", 1 << 40),
+];
+
+pub const AGE_UNITS: [(&str, u64); 5] = [
+    ("s", 1),
+    ("m", 60),
+    ("h", 60 * 60),
+    ("d", 60 * 60 * 24),
+    ("w", 60 * 60 * 24 * 7),
+];
+
+impl Amount {
+    /// Converts the amount to the base unit, i.e. bytes or seconds, where a
+    /// plain number is already in the base unit. Units are case insensitive,
+    /// except for the single letter age units. An amount that doesn't fit in
+    /// a u64 is invalid, rather than being capped.
+    pub fn value(&self, units: &[(&str, u64)]) -> Result<u64, MatcherError> {
+        let text = match self {
+            Amount::Number(number) => return Ok(*number),
+            Amount::Text(text) => text.trim(),
+        };
+
+        let split = text
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(text.len());
+        let (number, unit) = (text[..split].trim(), text[split..].trim());
+
+        let multiplier = match unit {
+            "" => Some(1),
+            unit => units
+                .iter()
+                .find(|(name, _)| {
+                    *name == unit || (name.len() > 1 && name.eq_ignore_ascii_case(unit))
+                })
+                .map(|(_, multiplier)| *multiplier),
+        };
+
+        match (number.parse::<f64>(), multiplier) {
+            (Ok(number), Some(multiplier)) if number * (multiplier as f64) < u64::MAX as f64 => {
+                Ok((number * multiplier as f64) as u64)
+            }
+            _ => Err(MatcherError::InvalidAmount(text.to_owned())),
+        }
+    }
+}
+
+impl std::fmt::Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Amount::Number(number) => write!(f, "{}", number),
+            Amount::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// The metadata of the file being opened, as far as it could be read.
+#[derive(Debug)]
+pub struct FileMetadata {
+    /// The metadata of the file, following symbolic links.
+    target: Option<Metadata>,
+    /// The metadata of the path itself, without following symbolic links.
+    link: Option<Metadata>,
+    hidden: bool,
+}
+
+impl FileMetadata {
+    fn read(file_path: &Path) -> FileMetadata {
+        let log_error = |e: std::io::Error| {
+            log::error!(
+                "Failed to read the metadata of file \"{}\" because: {}",
+                file_path.to_str().unwrap_or("CANNOT_GET_FILE"),
+                e
+            );
+        };
+
+        let target = std::fs::metadata(file_path).map_err(log_error).ok();
+        let link = std::fs::symlink_metadata(file_path).map_err(log_error).ok();
+
+        #[cfg(windows)]
+        let hidden = {
+            use std::os::windows::fs::MetadataExt;
+            const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+            link.as_ref()
+                .is_some_and(|m| m.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+        };
+
+        // Elsewhere, a file is hidden by convention when its name starts with
+        // a dot.
+        #[cfg(not(windows))]
+        let hidden = file_path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+
+        FileMetadata {
+            target,
+            link,
+            hidden,
+        }
+    }
+
+    fn check_size(
+        &self,
+        amount: Option<&Amount>,
+        compare: fn(u64, u64) -> bool,
+    ) -> Result<Option<Captures>, MatcherError> {
+        let limit = amount.map(|amount| amount.value(&SIZE_UNITS)).transpose()?;
+
+        Ok(self
+            .target
+            .as_ref()
+            .zip(limit)
+            .filter(|(metadata, limit)| compare(metadata.len(), *limit))
+            .map(|_| Captures::new()))
+    }
+
+    fn check_age(
+        &self,
+        amount: Option<&Amount>,
+        time: fn(&Metadata) -> std::io::Result<SystemTime>,
+        compare: fn(u64, u64) -> bool,
+    ) -> Result<Option<Captures>, MatcherError> {
+        let limit = amount.map(|amount| amount.value(&AGE_UNITS)).transpose()?;
+
+        let (metadata, limit) = match self.target.as_ref().zip(limit) {
+            Some(metadata_and_limit) => metadata_and_limit,
+            None => return Ok(None),
+        };
+
+        let time = time(metadata).map_err(|e| MatcherError::MetadataUnavailable(e.to_string()))?;
+
+        // A time in the future, e.g. due to clock skew, counts as no age.
+        let age = SystemTime::now()
+            .duration_since(time)
+            .unwrap_or_default()
+            .as_secs();
+
+        Ok(compare(age, limit).then(Captures::new))
+    }
+
+    fn check_flag(
+        &self,
+        expected: Option<bool>,
+        flag: fn(&FileMetadata) -> Option<bool>,
+    ) -> Option<Captures> {
+        (flag(self)? == expected?).then(Captures::new)
+    }
+}
+
+/// How many bytes are read at a time when streaming the content of a file.
+const STREAM_CHUNK: u64 = 1 << 20;
+
+/// How many bytes at the end of a streamed chunk are searched again, along
+/// with the next chunk, which is also the longest match that is guaranteed
+/// to be found.
+const STREAM_OVERLAP: usize = 64 << 10;
+
+/// How many bytes before the start of a streamed search are kept, so that
+/// assertions such as `^` and `\b` can look at the character before it.
+const STREAM_CONTEXT: usize = 4;
+
+/// A deferred evaluation of a matcher condition.
+type ConditionCheck<'a> = &'a dyn Fn() -> Result<Option<Captures>, MatcherError>;
+
+/// The groups captured by a matcher's RegEx, keyed by both their name (if
+/// they have one) and their index. Groups that didn't participate in the
+/// match are present, but empty.
+pub type Captures = HashMap<String, String>;
+
+impl Matcher {
+    fn rmatch_file(
+        pattern: Option<&Pattern<re::Regex>>,
+        content: &str,
+    ) -> Result<Option<Captures>, MatcherError> {
+        let regex = pattern.ok_or(MatcherError::NoRegexError)?.regex()?;
+        Ok(Matcher::collect_captures(regex, content))
+    }
+
+    fn collect_captures(regex: &re::Regex, content: &str) -> Option<Captures> {
+        let captures = regex.captures(content)?;
+        let mut collected = Captures::new();
+
+        for (index, name) in regex.capture_names().enumerate() {
+            let value = captures
+                .get(index)
+                .map_or_else(String::new, |m| String::from(m.as_str()));
+
+            if let Some(name) = name {
+                collected.insert(String::from(name), value.to_owned());
+            }
+
+            collected.insert(index.to_string(), value);
         }
+
+        Some(collected)
     }
-}
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct FassocRules {
-    pub mappings: HashMap<String, Vec<String>>,
-    pub matchers: HashMap<String, Matcher>,
-    pub commands: HashMap<String, Command>,
-}
+    fn gmatch(
+        &self,
+        globs: Option<&Globs>,
+        subject: impl AsRef<Path>,
+    ) -> Result<Option<Captures>, MatcherError> {
+        let case_sensitive = self.case_sensitive.unwrap_or(!cfg!(windows));
+        let mut builder = gs::GlobSetBuilder::new();
 
-impl FassocRules {
-    pub fn find_suitable_command(&self, file_path: &Path) -> Result<&Command, FindCommandError> {
-        let file_name_str: String = file_path.file_name().and_then(|n| n.to_str()).map_or_else(
-            || Err(FindCommandError::CannotConvertPath),
-            |s| Ok(String::from(s)),
-        )?;
+        for pattern in globs.map(Globs::items).unwrap_or_default() {
+            builder.add(
+                gs::GlobBuilder::new(pattern)
+                    .case_insensitive(!case_sensitive)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(MatcherError::InvalidGlob)?,
+            );
+        }
 
-        // File extension is allowed to be None, as it could stil be handled
-        // by the fallback catch-all mapping "*"
-        let file_ext_str = file_path
-            .extension()
-            .and_then(|ext| ext.to_str().map(|s| String::from(s)));
+        let set = builder.build().map_err(MatcherError::InvalidGlob)?;
+        Ok(set.is_match(subject).then(Captures::new))
+    }
 
-        let fallback_mapping = self.mappings.get(&String::from("*"));
-        let extension_mapping = file_ext_str.to_owned().and_then(|s| self.mappings.get(&s));
+    fn rmatch_path(
+        pattern: Option<&Pattern<re::Regex>>,
+        path: &Path,
+    ) -> Result<Option<Captures>, MatcherError> {
+        Matcher::rmatch_file(pattern, &path.to_string_lossy())
+    }
 
-        // Use the mapping derived from the file extension, if found, otherwise
-        // use the fallback mapping, if found.
-        let final_mapping = match extension_mapping.or(fallback_mapping) {
-            Some(mapping) => mapping,
-            None => {
-                // Neither the extension mapping nor the fallback mapping found.
-                return Err(FindCommandError::NoMappingFound);
-            }
-        };
+    fn magic_patterns(magic: &OneOrMany<Signature>) -> Result<Vec<BytePattern>, MatcherError> {
+        let signatures = magic.items();
+        let patterns = signatures
+            .iter()
+            .map(Signature::patterns)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(patterns.into_iter().flatten().collect())
+    }
 
-        // File content is stored, so that it doesn't have to be read multiple
-        // times. Reading is avoided unless needed, for performance reasons.
-        let mut file_content: &mut Option<String> = &mut None;
-
-        let ensure_contents_read = |content: &mut Option<String>| {
-            if content.is_none() {
-                *content = std::fs::read_to_string(file_path).map_or_else(
-                    |e| {
-                        log::error!(
-                            "Failed to read the contents of file \"{}\" because: {}",
-                            file_path.to_str().unwrap_or("CANNOT_GET_FILE"),
-                            e
-                        );
-                        None
-                    },
-                    |s| Some(s),
-                );
-            }
+    /// Where the signature ends in the file, or None if that lies beyond what
+    /// can be addressed, in which case the signature can never match.
+    fn signature_end((offset, bytes): &BytePattern) -> Option<usize> {
+        usize::try_from(*offset).ok()?.checked_add(bytes.len())
+    }
+
+    fn match_signature(pattern: &BytePattern, head: &[u8]) -> bool {
+        let bytes: &Vec<Option<u8>> = &pattern.1;
+
+        let range = match Matcher::signature_end(pattern) {
+            Some(end) => end - bytes.len()..end,
+            None => return false,
         };
 
-        let mapping_name = file_ext_str.to_owned().unwrap_or_else(|| String::from("*"));
+        head.get(range).is_some_and(|slice| {
+            slice
+                .iter()
+                .zip(bytes)
+                .all(|(byte, expected)| expected.is_none_or(|expected| *byte == expected))
+        })
+    }
 
-        for (index, matcher_name) in final_mapping.iter().enumerate() {
-            log::debug!("Trying matcher #{} - {}", index, matcher_name);
+    /// Checks whether the path lies beneath the directory of the `under`
+    /// condition, once both have been normalised. Environment variables in
+    /// the directory are expanded according to the `undefined_variables`
+    /// option.
+    pub fn is_under(
+        directory: &str,
+        undefined: UndefinedVariables,
+        path: &Path,
+    ) -> Result<Option<Captures>, MatcherError> {
+        let variables = Variables {
+            undefined,
+            ..Variables::default()
+        };
+        let directory =
+            substitute(directory, &variables).map_err(MatcherError::InvalidDirectory)?;
+        let directory = normalize_path(Path::new(&directory));
 
-            let matcher: &Matcher = match self.matchers.get(matcher_name) {
-                Some(matcher) => matcher,
-                None => {
-                    match self.commands.get(matcher_name) {
-                        Some(command) => {
-                            log::debug!(
-                                "Mapping \"{}\" referred to \"{}\" which isn't a valid matcher, but it is a valid command, mapping directly to command instead.", 
-                                mapping_name,
-                                matcher_name
-                            );
-
-                            return Ok(command);
-                        },
-
-                        None => {
-                            log::warn!(
-                                "Ignored a matcher with mame \"{}\" from mapping \"{}\" because it doesn't point to anything that exists.", 
-                                matcher_name, 
-                                mapping_name
-                            );
-
-                            continue;
-                        }
-                    }
+        let is_under = if cfg!(windows) {
+            let lowercase = |path: &Path| PathBuf::from(path.to_string_lossy().to_lowercase());
+            lowercase(path).starts_with(lowercase(&directory))
+        } else {
+            path.starts_with(&directory)
+        };
 
-                }
-            };
+        Ok(is_under.then(Captures::new))
+    }
 
-            let matcher_command: &Command = match self.commands.get(&matcher.command) {
-                Some(command) => command,
-                None => {
-                    log::warn!("The command pointed to by matcher \"{}\" does not exist, ignoring this matcher.", matcher_name);
-                    continue;
-                }
-            };
+    /// Like `collect_captures`, but for content that isn't necessarily valid
+    /// UTF-8, where invalid sequences are replaced in the captured values.
+    fn collect_byte_captures(regex: &re::bytes::Regex, content: &[u8]) -> Option<Captures> {
+        Some(Matcher::convert_byte_captures(
+            regex,
+            &regex.captures(content)?,
+        ))
+    }
 
-            let mut is_match: bool = true;
+    fn convert_byte_captures(regex: &re::bytes::Regex, captures: &re::bytes::Captures) -> Captures {
+        let mut collected = Captures::new();
 
-            // If matcher has file name RegEx, match the RegEx against the file.
-            is_match &= matcher.regexf.as_ref().map_or(true, |_| {
-                matcher.rmatch_file_name(file_name_str.to_owned())
-                    .unwrap_or_else(|error| {
-                        log::error!(
-                            "Encountered RegEx error when evaluating mapped matchers: {:?}",
-                            error
-                        );
-                        false
-                    })
+        for (index, name) in regex.capture_names().enumerate() {
+            let value = captures.get(index).map_or_else(String::new, |m| {
+                String::from_utf8_lossy(m.as_bytes()).into_owned()
             });
 
-            // If match already failed, continue.
-            if !is_match {
-                continue;
+            if let Some(name) = name {
+                collected.insert(String::from(name), value.to_owned());
             }
 
-            // If matcher has file content RegEx, match the RegEx against the file.
-            is_match &= matcher.regexc.as_ref().map_or(true, |_| {
-                ensure_contents_read(&mut file_content);
+            collected.insert(index.to_string(), value);
+        }
 
-                file_content.as_ref().map_or(false, |content| {
-                    matcher.rmatch_file_content(content).unwrap_or_else(|error| {
-                        log::error!(
-                            "Encountered RegEx error when evaluating mapped matchers: {:?}",
-                            error,
-                        );
-                        false
-                    })
-                })
-            });
+        collected
+    }
 
-            // If the matcher is still valid after validation, return it.
-            if is_match {
-                log::debug!(
-                    "Matcher #{} - {} - matched this file.",
-                    index,
-                    matcher_name
-                );
+    pub fn rmatch_file_content(
+        pattern: &Pattern<re::bytes::Regex>,
+        file_content: &[u8],
+    ) -> Result<Option<Captures>, MatcherError> {
+        Ok(Matcher::collect_byte_captures(
+            pattern.regex()?,
+            file_content,
+        ))
+    }
 
+    /// Matches the content RegEx against the whole file, reading it in chunks,
+    /// rather than holding all of it in memory.
+    pub fn rmatch_file_stream(
+        pattern: &Pattern<re::bytes::Regex>,
+        file_path: &Path,
+    ) -> Result<Option<Captures>, MatcherError> {
+        let regex = pattern.regex()?;
 
-                return Ok(matcher_command);
-            }
-        }
+        let result =
+            std::fs::File::open(file_path).and_then(|file| Matcher::rmatch_stream(regex, file));
 
-        return Err(FindCommandError::NoMatchFound);
+        Ok(result.unwrap_or_else(|e| {
+            log::error!(
+                "Failed to read the contents of file \"{}\" because: {}",
+                file_path.to_str().unwrap_or("CANNOT_GET_FILE"),
+                e
+            );
+
+            None
+        }))
     }
-}
 
-// ----------------------------------------------------------------------------
-// Matcher
-// ----------------------------------------------------------------------------
-#[derive(Debug)]
-pub enum MatcherError {
-    RegexCompileError(re::Error),
-    NoRegexError,
-}
+    /// Matches the RegEx against everything that can be read from the reader,
+    /// one chunk at a time, where the end of the previous chunk is kept in
+    /// front of the next one, so that matches of up to `STREAM_OVERLAP` bytes
+    /// aren't missed when they span two chunks.
+    ///
+    /// Anchors only match where they would in the whole content: the search
+    /// starts after some retained context, so that `^` and `\A` don't match
+    /// at the start of a chunk, and matches that reach into the last
+    /// `STREAM_OVERLAP` bytes of the window are deferred to the next one, so
+    /// that `$` and `\z` don't match at the end of a chunk.
+    fn rmatch_stream(
+        regex: &re::bytes::Regex,
+        mut reader: impl Read,
+    ) -> std::io::Result<Option<Captures>> {
+        let mut window: Vec<u8> = Vec::new();
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Matcher {
-    pub command: String,
-    pub regexf: Option<String>,
-    pub regexc: Option<String>,
+        // Where in the window the search starts, as everything before it has
+        // either been searched already, or is only kept as context.
+        let mut search_start: usize = 0;
+
+        loop {
+            let read = (&mut reader).take(STREAM_CHUNK).read_to_end(&mut window)?;
+            let at_end: bool = (read as u64) < STREAM_CHUNK;
+
+            let limit: usize = if at_end {
+                window.len()
+            } else {
+                window.len().saturating_sub(STREAM_OVERLAP)
+            };
+
+            let found = regex.captures_at(&window, search_start);
+            let found_match = found.as_ref().and_then(|captures| captures.get(0));
+
+            if let (Some(captures), Some(found_match)) = (&found, found_match) {
+                if found_match.end() <= limit {
+                    return Ok(Some(Matcher::convert_byte_captures(regex, captures)));
+                }
+            }
+
+            if at_end {
+                return Ok(None);
+            }
+
+            // A deferred match is searched for again from where it started,
+            // as long as it isn't longer than STREAM_OVERLAP, which keeps the
+            // window from growing without bounds.
+            let next_start: usize = found_match
+                .map_or(limit, |found_match| {
+                    found_match
+                        .start()
+                        .clamp(limit.saturating_sub(STREAM_OVERLAP), limit)
+                })
+                .max(search_start);
+
+            let kept: usize = next_start.saturating_sub(STREAM_CONTEXT);
+            window.drain(..kept);
+            search_start = next_start - kept;
+        }
+    }
 }
 
-impl Matcher {
-    fn rmatch_file(regstr: Option<String>, content: &String) -> Result<bool, MatcherError> {
-        regstr.map_or(Err(MatcherError::NoRegexError), |regstr| {
-            re::Regex::new(regstr.as_str()).map_or_else(
-                |error| Err(MatcherError::RegexCompileError(error)),
-                |regex| Ok(regex.is_match(content.as_str())),
+/// Reads up to `length` bytes from the end of the file, returning nothing if
+/// it cannot be read.
+fn read_tail(file_path: &Path, length: usize) -> Vec<u8> {
+    let mut tail: Vec<u8> = Vec::with_capacity(length);
+
+    std::fs::File::open(file_path)
+        .and_then(|mut file| {
+            let size = file.metadata()?.len();
+            file.seek(SeekFrom::Start(size.saturating_sub(length as u64)))?;
+            file.read_to_end(&mut tail)
+        })
+        .map_err(|e| {
+            log::error!(
+                "Failed to read the end of file \"{}\" because: {}",
+                file_path.to_str().unwrap_or("CANNOT_GET_FILE"),
+                e
             )
         })
-    }
+        .ok();
 
-    pub fn rmatch_file_name(&self, file_name: String) -> Result<bool, MatcherError> {
-        Matcher::rmatch_file(self.regexf.to_owned(), &file_name)
-    }
+    tail
+}
+
+/// Makes the path absolute, and resolves its "." and ".." components without
+/// touching the file system, so symbolic links are left as they are.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut normalized = PathBuf::new();
 
-    pub fn rmatch_file_content(&self, file_content: &String) -> Result<bool, MatcherError> {
-        Matcher::rmatch_file(self.regexc.to_owned(), file_content)
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
     }
+
+    normalized
 }
 
 // ----------------------------------------------------------------------------
@@ -221,7 +1892,7 @@ pub struct Command {
     pub thread_attributes: Option<SecurityAttributes>,
     pub inherit_handles: Option<bool>,
     pub creation_flags: Option<Vec<sj::Value>>,
-    // pub environment: Option<Vec<sj::Value>>, -- This will be implemented later.
+    pub environment: Option<Environment>,
     pub extras: Option<Extras>,
 }
 
@@ -235,8 +1906,9 @@ impl Clone for Command {
             cwd: self.cwd.clone(),
             process_attributes: self.process_attributes.clone(),
             thread_attributes: self.thread_attributes.clone(),
-            inherit_handles: self.inherit_handles.clone(),
+            inherit_handles: self.inherit_handles,
             creation_flags: self.creation_flags.clone(),
+            environment: self.environment.clone(),
             extras: self.extras.clone(),
         }
     }
@@ -257,8 +1929,105 @@ pub struct SecurityAttributes {
 impl Clone for SecurityAttributes {
     fn clone(&self) -> Self {
         SecurityAttributes {
-            security_descriptor: self.security_descriptor.clone(),
-            inherit_handle: self.inherit_handle.clone(),
+            security_descriptor: self.security_descriptor,
+            inherit_handle: self.inherit_handle,
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Environment
+// ----------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Environment {
+    /// Start from an empty environment, rather than inheriting it.
+    pub clear: Option<bool>,
+    pub set: Option<HashMap<String, String>>,
+    pub unset: Option<Vec<String>>,
+    /// Values to prepend to PATH-like variables, using the path separator.
+    pub prepend: Option<HashMap<String, String>>,
+    /// Values to append to PATH-like variables, using the path separator.
+    pub append: Option<HashMap<String, String>>,
+}
+
+impl Environment {
+    /// Produces the complete set of variables the process should receive,
+    /// by applying the unset, set, prepend, and append operations, in that
+    /// order, to either the inherited environment or an empty one. Inherited
+    /// variables are passed on as they are, even if they aren't valid Unicode.
+    pub fn resolve(&self) -> Vec<(OsString, OsString)> {
+        let mut variables: Vec<(OsString, OsString)> = if self.clear.unwrap_or(false) {
+            Vec::new()
+        } else {
+            std::env::vars_os().collect()
+        };
+
+        // Variable names are case insensitive on Windows.
+        let same_name = |a: &OsStr, b: &str| {
+            if cfg!(windows) {
+                a.eq_ignore_ascii_case(b)
+            } else {
+                a == b
+            }
+        };
+        let separator = if cfg!(windows) { ";" } else { ":" };
+
+        for name in self.unset.iter().flatten() {
+            variables.retain(|(k, _)| !same_name(k, name));
+        }
+
+        let mut apply = |name: &str, value: &dyn Fn(Option<&OsStr>) -> OsString| match variables
+            .iter_mut()
+            .find(|(k, _)| same_name(k, name))
+        {
+            Some((_, existing)) => *existing = value(Some(existing)),
+            None => variables.push((OsString::from(name), value(None))),
+        };
+
+        let join = |first: &OsStr, second: &OsStr| {
+            let mut joined = OsString::from(first);
+            joined.push(separator);
+            joined.push(second);
+            joined
+        };
+
+        for (name, value) in self.set.iter().flatten() {
+            apply(name, &|_| OsString::from(value));
+        }
+
+        for (name, value) in self.prepend.iter().flatten() {
+            apply(name, &|existing| match existing {
+                Some(existing) if !existing.is_empty() => join(OsStr::new(value), existing),
+                _ => OsString::from(value),
+            });
+        }
+
+        for (name, value) in self.append.iter().flatten() {
+            apply(name, &|existing| match existing {
+                Some(existing) if !existing.is_empty() => join(existing, OsStr::new(value)),
+                _ => OsString::from(value),
+            });
+        }
+
+        // The depth is always passed on, even to an empty environment, as it
+        // is what stops fassoc-proxy from launching itself in a loop.
+        if let Some(depth) = std::env::var_os(DEPTH_VARIABLE) {
+            apply(DEPTH_VARIABLE, &|_| depth.to_owned());
+        }
+
+        variables
+    }
+}
+
+impl Clone for Environment {
+    fn clone(&self) -> Self {
+        Environment {
+            clear: self.clear,
+            set: self.set.clone(),
+            unset: self.unset.clone(),
+            prepend: self.prepend.clone(),
+            append: self.append.clone(),
         }
     }
 }
@@ -289,15 +2058,565 @@ impl Clone for Extras {
         Extras {
             desktop: self.desktop.clone(),
             title: self.title.clone(),
-            x: self.x.clone(),
-            y: self.y.clone(),
-            x_size: self.x_size.clone(),
-            y_size: self.y_size.clone(),
-            x_count_chars: self.x_count_chars.clone(),
-            y_count_chars: self.y_count_chars.clone(),
+            x: self.x,
+            y: self.y,
+            x_size: self.x_size,
+            y_size: self.y_size,
+            x_count_chars: self.x_count_chars,
+            y_count_chars: self.y_count_chars,
             fill_attribute: self.fill_attribute.clone(),
             flags: self.flags.clone(),
             show_window: self.show_window.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK: usize = STREAM_CHUNK as usize;
+
+    /// Content of the given length filled with "a", with the given bytes
+    /// written over it at the given positions.
+    fn filled(length: usize, inserts: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut content = vec![b'a'; length];
+
+        for (position, bytes) in inserts {
+            content[*position..*position + bytes.len()].copy_from_slice(bytes);
+        }
+
+        content
+    }
+
+    fn stream(source: &str, content: &[u8]) -> Option<String> {
+        let regex = re::bytes::Regex::new(source).unwrap();
+        let captures = Matcher::rmatch_stream(&regex, content).unwrap();
+        captures.map(|captures| captures["0"].to_owned())
+    }
+
+    /// Writes a file to the temporary directory, named after the test so that
+    /// tests running in parallel don't share files.
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("fassoc-proxy-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn rules(value: sj::Value) -> FassocRules {
+        sj::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn stream_matches_across_chunks() {
+        let content = filled(2 * CHUNK, &[(CHUNK - 3, b"NEEDLE")]);
+        assert_eq!(stream("NEEDLE", &content).as_deref(), Some("NEEDLE"));
+
+        // A match that starts before the overlap, and ends within it.
+        let long: Vec<u8> = [b"B".as_slice(), &[b'c'; 1000], b"E"].concat();
+        let content = filled(2 * CHUNK, &[(CHUNK - STREAM_OVERLAP - 10, &long)]);
+        assert_eq!(
+            stream("Bc+E", &content).map(|found| found.len()),
+            Some(long.len())
+        );
+    }
+
+    #[test]
+    fn stream_matches_in_last_chunk() {
+        let content = filled(3 * CHUNK + 5, &[(3 * CHUNK + 1, b"END")]);
+        assert_eq!(stream("END", &content).as_deref(), Some("END"));
+        assert_eq!(stream("a\\z", &content).as_deref(), Some("a"));
+        assert_eq!(stream("NONE", &content), None);
+    }
+
+    #[test]
+    fn stream_end_anchors_ignore_chunk_edges() {
+        let content = filled(CHUNK + 10, &[(CHUNK - 1, b"x")]);
+
+        assert_eq!(stream("x$", &content), None);
+        assert_eq!(stream("x\\z", &content), None);
+        assert_eq!(stream("(?m)x$", &content), None);
+        assert_eq!(stream("a\\z", &content).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn stream_start_anchors_ignore_chunk_edges() {
+        let edges = [
+            CHUNK - STREAM_OVERLAP,
+            CHUNK,
+            2 * CHUNK - 2 * STREAM_OVERLAP,
+            2 * CHUNK - STREAM_OVERLAP,
+        ];
+        let inserts: Vec<(usize, &[u8])> =
+            edges.iter().map(|edge| (*edge, b"x".as_slice())).collect();
+        let content = filled(3 * CHUNK, &inserts);
+
+        assert_eq!(stream("\\Ax", &content), None);
+        assert_eq!(stream("^x", &content), None);
+        assert_eq!(stream("(?m)^x", &content), None);
+        assert_eq!(stream("\\bx", &content), None);
+        assert_eq!(stream("\\Aa", &content).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn stream_multi_line_anchors_at_chunk_edges() {
+        let content = filled(2 * CHUNK, &[(CHUNK - STREAM_OVERLAP - 1, b"\nx")]);
+        assert_eq!(stream("(?m)^x", &content).as_deref(), Some("x"));
+
+        let content = filled(2 * CHUNK, &[(CHUNK - 1, b"x\n")]);
+        assert_eq!(stream("(?m)x$", &content).as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn content_limit_cuts_content_to_prefix() {
+        let path = temp_file("content-limit.txt", b"0123456789NEEDLE");
+        let rules_with = |limit: u64, stream: bool| {
+            rules(sj::json!({
+                "mappings": { "txt": ["M"] },
+                "matchers": { "M": { "command": "C", "regexc": "NEEDLE", "content_limit": limit, "content_stream": stream } },
+                "commands": { "C": { "path": "/bin/true" } }
+            }))
+        };
+
+        let cut = rules_with(10, false);
+        assert!(matches!(
+            cut.search_commands(&path).next_command(),
+            Err(FindCommandError::NoMatchFound)
+        ));
+
+        let whole = rules_with(16, false);
+        assert_eq!(
+            whole.search_commands(&path).next_command().unwrap().name,
+            "C"
+        );
+
+        // Streaming always reads the whole file.
+        let streamed = rules_with(10, true);
+        assert_eq!(
+            streamed.search_commands(&path).next_command().unwrap().name,
+            "C"
+        );
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn under_follows_undefined_variables() {
+        let path = temp_file("under.txt", b"");
+        let directory = format!("%FASSOC_TEST_UNDEFINED%{}", std::env::temp_dir().display());
+        let rules_with = |undefined: &str| {
+            rules(sj::json!({
+                "mappings": { "txt": ["M"] },
+                "matchers": { "M": { "command": "C", "under": directory } },
+                "commands": { "C": { "path": "/bin/true" } },
+                "options": { "undefined_variables": undefined }
+            }))
+        };
+
+        let empty = rules_with("empty");
+        assert_eq!(
+            empty.search_commands(&path).next_command().unwrap().name,
+            "C"
+        );
+
+        for undefined in ["literal", "error"] {
+            let rules = rules_with(undefined);
+            let mut search = rules.search_commands(&path);
+            assert!(matches!(
+                search.next_command(),
+                Err(FindCommandError::NoMatchFound)
+            ));
+
+            let condition = &search.trace.candidates[0].conditions[0];
+            assert_eq!(condition.error.is_some(), undefined == "error");
+        }
+
+        std::fs::remove_file(path).ok();
+    }
+
+    fn dispatch_rules(mappings: sj::Value) -> FassocRules {
+        rules(sj::json!({
+            "mappings": mappings,
+            "matchers": {
+                "Never": { "command": "A", "regexf": "^$" },
+                "Always": { "command": "B", "regexf": "txt" }
+            },
+            "commands": { "A": { "path": "a" }, "B": { "path": "b" }, "X": { "path": "x" } }
+        }))
+    }
+
+    /// The names of the commands the search selects, in order, as if each of
+    /// them failed to launch, followed by the error the search ends with.
+    fn dispatch(rules: &FassocRules, file: &str) -> (Vec<String>, FindCommandError) {
+        let mut search = rules.search_commands(Path::new(file));
+        let mut selected: Vec<String> = Vec::new();
+
+        loop {
+            match search.next_command() {
+                Ok(selection) => selected.push(selection.name.to_owned()),
+                Err(error) => return (selected, error),
+            }
+        }
+    }
+
+    #[test]
+    fn next_candidate_after_launch_failure() {
+        let rules = dispatch_rules(sj::json!({ "txt": ["A", "Never", "Always", "X"] }));
+        let (selected, error) = dispatch(&rules, "notes.txt");
+
+        assert_eq!(selected, ["A", "B", "X"]);
+        assert!(matches!(error, FindCommandError::NoMatchFound));
+    }
+
+    #[test]
+    fn fallback_only_after_launch_failure() {
+        let rules = dispatch_rules(sj::json!({ "txt": ["Never"], "md": ["Always"], "*": ["X"] }));
+
+        // The mapping of the extension applies, but none of its candidates
+        // match, so the fallback mapping isn't used.
+        let (selected, error) = dispatch(&rules, "notes.txt");
+        assert!(selected.is_empty());
+        assert!(matches!(error, FindCommandError::NoMatchFound));
+
+        // Once a candidate fails to launch, the fallback mapping is used.
+        let rules = dispatch_rules(sj::json!({ "txt": ["Always"], "*": ["X", "A"] }));
+        let (selected, error) = dispatch(&rules, "notes.txt");
+        assert_eq!(selected, ["B", "X", "A"]);
+        assert!(matches!(error, FindCommandError::NoMatchFound));
+    }
+
+    #[test]
+    fn fallback_without_mapping() {
+        let rules = dispatch_rules(sj::json!({ "txt": ["Always"], "*": ["X"] }));
+
+        for file in ["notes.md", "notes"] {
+            let (selected, error) = dispatch(&rules, file);
+            assert_eq!(selected, ["X"]);
+            assert!(matches!(error, FindCommandError::NoMatchFound));
+        }
+    }
+
+    #[test]
+    fn no_mapping_found() {
+        let rules = dispatch_rules(sj::json!({ "txt": ["Always"] }));
+
+        for file in ["notes.md", "notes"] {
+            let (selected, error) = dispatch(&rules, file);
+            assert!(selected.is_empty());
+            assert!(matches!(error, FindCommandError::NoMappingFound));
+        }
+    }
+
+    #[test]
+    fn candidates_are_traced() {
+        let rules = dispatch_rules(sj::json!({ "txt": ["Never", "Always"], "*": ["X"] }));
+        let mut search = rules.search_commands(Path::new("notes.txt"));
+
+        assert_eq!(search.next_command().unwrap().name, "B");
+        assert_eq!(search.next_command().unwrap().name, "X");
+        assert_eq!(search.trace.mappings, ["txt", "*"]);
+        assert_eq!(search.trace.candidates.len(), 3);
+    }
+
+    fn signature(value: sj::Value) -> Signature {
+        sj::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn hex_signatures() {
+        let parsed = signature(sj::json!("50 4b0304")).patterns().unwrap();
+        assert_eq!(
+            parsed,
+            [(0, vec![Some(0x50), Some(0x4B), Some(0x03), Some(0x04)])]
+        );
+
+        let parsed = signature(sj::json!({ "bytes": "ff ?? 00", "offset": 4 }))
+            .patterns()
+            .unwrap();
+        assert_eq!(parsed, [(4, vec![Some(0xFF), None, Some(0x00)])]);
+
+        for invalid in ["", "5", "50 4", "zz", "?0", "50 ?"] {
+            assert!(
+                matches!(
+                    signature(sj::json!(invalid)).patterns(),
+                    Err(MatcherError::InvalidSignature(_))
+                ),
+                "{:?} should be invalid",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn builtin_signatures() {
+        assert_eq!(signature(sj::json!("ZIP")).patterns().unwrap().len(), 3);
+        assert_eq!(
+            signature(sj::json!("png")).patterns().unwrap()[0].1[1],
+            Some(b'P')
+        );
+
+        // A name that isn't known is read as hexadecimal bytes.
+        assert_eq!(
+            signature(sj::json!("be")).patterns().unwrap(),
+            [(0, vec![Some(0xBE)])]
+        );
+    }
+
+    #[test]
+    fn signature_offset_limit() {
+        let at_limit = sj::json!({ "bytes": "00", "offset": MAX_SIGNATURE_OFFSET });
+        assert!(signature(at_limit).patterns().is_ok());
+
+        let beyond = sj::json!({ "bytes": "00", "offset": MAX_SIGNATURE_OFFSET + 1 });
+        assert!(matches!(
+            signature(beyond).patterns(),
+            Err(MatcherError::InvalidOffset(offset)) if offset == MAX_SIGNATURE_OFFSET + 1
+        ));
+
+        let huge = sj::json!({ "bytes": "00", "offset": u64::MAX });
+        assert!(matches!(
+            signature(huge).patterns(),
+            Err(MatcherError::InvalidOffset(u64::MAX))
+        ));
+    }
+
+    #[test]
+    fn signature_end_overflow() {
+        assert_eq!(Matcher::signature_end(&(4, vec![None; 2])), Some(6));
+        assert_eq!(Matcher::signature_end(&(u64::MAX, vec![None; 2])), None);
+        assert!(!Matcher::match_signature(&(u64::MAX, vec![None]), &[0; 16]));
+    }
+
+    #[test]
+    fn signatures_match_head() {
+        let head = [0x89, b'P', b'N', b'G', 0x0D, 0x0A];
+        let pattern = |offset: u64, bytes: &[Option<u8>]| (offset, bytes.to_vec());
+
+        assert!(Matcher::match_signature(
+            &pattern(0, &[Some(0x89), None, Some(b'N')]),
+            &head
+        ));
+        assert!(Matcher::match_signature(
+            &pattern(4, &[Some(0x0D), Some(0x0A)]),
+            &head
+        ));
+        assert!(!Matcher::match_signature(&pattern(1, &[Some(0x89)]), &head));
+
+        // A signature that reaches past the end of the head never matches.
+        assert!(!Matcher::match_signature(
+            &pattern(5, &[Some(0x0A), None]),
+            &head
+        ));
+    }
+
+    #[test]
+    fn magic_reads_up_to_the_signature() {
+        let mut content = vec![0u8; 40000];
+        content[32769..32774].copy_from_slice(b"CD001");
+        let path = temp_file("magic.dat", &content);
+
+        let rules = rules(sj::json!({
+            "mappings": { "dat": ["Iso"] },
+            "matchers": { "Iso": { "command": "C", "magic": [ "png", { "bytes": "43 44 30 30 31", "offset": 32769 } ] } },
+            "commands": { "C": { "path": "/bin/true" } }
+        }));
+
+        assert_eq!(
+            rules.search_commands(&path).next_command().unwrap().name,
+            "C"
+        );
+        std::fs::remove_file(path).ok();
+    }
+
+    fn environment(value: sj::Value) -> Environment {
+        sj::from_value(value).unwrap()
+    }
+
+    fn lookup<'v>(variables: &'v [(OsString, OsString)], name: &str) -> Option<&'v OsStr> {
+        variables
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_os_str())
+    }
+
+    #[test]
+    fn environment_resolve() {
+        // The variables of the process are shared by every test, so the ones
+        // used here are unique to this test.
+        std::env::set_var("FASSOC_TEST_KEEP", "keep");
+        std::env::set_var("FASSOC_TEST_UNSET", "unset");
+        std::env::set_var("FASSOC_TEST_LIST", "b");
+        std::env::set_var(DEPTH_VARIABLE, "2");
+
+        let separator = if cfg!(windows) { ";" } else { ":" };
+
+        let inherited = environment(sj::json!({
+            "set": { "FASSOC_TEST_KEEP": "changed", "FASSOC_TEST_NEW": "new" },
+            "unset": ["FASSOC_TEST_UNSET", DEPTH_VARIABLE],
+            "prepend": { "FASSOC_TEST_LIST": "a" },
+            "append": { "FASSOC_TEST_LIST": "c", "FASSOC_TEST_EMPTY": "only" }
+        }))
+        .resolve();
+
+        assert_eq!(
+            lookup(&inherited, "FASSOC_TEST_KEEP"),
+            Some(OsStr::new("changed"))
+        );
+        assert_eq!(
+            lookup(&inherited, "FASSOC_TEST_NEW"),
+            Some(OsStr::new("new"))
+        );
+        assert_eq!(lookup(&inherited, "FASSOC_TEST_UNSET"), None);
+        assert_eq!(
+            lookup(&inherited, "FASSOC_TEST_LIST"),
+            Some(OsStr::new(&format!("a{}b{}c", separator, separator)))
+        );
+        assert_eq!(
+            lookup(&inherited, "FASSOC_TEST_EMPTY"),
+            Some(OsStr::new("only"))
+        );
+
+        // The depth is passed on, even though it was unset.
+        assert_eq!(lookup(&inherited, DEPTH_VARIABLE), Some(OsStr::new("2")));
+
+        let cleared =
+            environment(sj::json!({ "clear": true, "set": { "FASSOC_TEST_NEW": "new" } }))
+                .resolve();
+
+        assert_eq!(lookup(&cleared, "FASSOC_TEST_KEEP"), None);
+        assert_eq!(lookup(&cleared, "FASSOC_TEST_NEW"), Some(OsStr::new("new")));
+        assert_eq!(lookup(&cleared, DEPTH_VARIABLE), Some(OsStr::new("2")));
+        assert_eq!(cleared.len(), 2);
+
+        // Variables that aren't valid Unicode are inherited as they are.
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+
+            let invalid = OsStr::from_bytes(b"\xFF\xFE");
+            std::env::set_var("FASSOC_TEST_INVALID", invalid);
+
+            let inherited = environment(sj::json!({})).resolve();
+            assert_eq!(lookup(&inherited, "FASSOC_TEST_INVALID"), Some(invalid));
+        }
+    }
+
+    fn amount(text: &str) -> Result<u64, MatcherError> {
+        Amount::Text(text.to_owned()).value(&SIZE_UNITS)
+    }
+
+    fn age(text: &str) -> Result<u64, MatcherError> {
+        Amount::Text(text.to_owned()).value(&AGE_UNITS)
+    }
+
+    #[test]
+    fn amount_units() {
+        assert_eq!(Amount::Number(1234).value(&SIZE_UNITS).unwrap(), 1234);
+        assert_eq!(amount("1234").unwrap(), 1234);
+        assert_eq!(amount("50B").unwrap(), 50);
+        assert_eq!(amount("2KB").unwrap(), 2000);
+        assert_eq!(amount("2kb").unwrap(), 2000);
+        assert_eq!(amount(" 1.5 MiB ").unwrap(), 3 << 19);
+        assert_eq!(amount("1TiB").unwrap(), 1 << 40);
+
+        assert_eq!(age("30s").unwrap(), 30);
+        assert_eq!(age("2m").unwrap(), 120);
+        assert_eq!(age("1.5h").unwrap(), 5400);
+        assert_eq!(age("7d").unwrap(), 7 * 24 * 60 * 60);
+        assert_eq!(age("1w").unwrap(), 7 * 24 * 60 * 60);
+    }
+
+    #[test]
+    fn amount_rejects_unknown_units() {
+        for text in ["50 MBs", "50M", "MB", "", "1.2.3KB", "-5KB", "5 KB B"] {
+            assert!(
+                matches!(amount(text), Err(MatcherError::InvalidAmount(_))),
+                "{:?} was accepted",
+                text
+            );
+        }
+
+        // Single letter age units are case sensitive, and size units don't
+        // apply to ages.
+        assert!(age("7D").is_err());
+        assert!(age("1H").is_err());
+        assert!(age("1KB").is_err());
+        assert!(amount("7d").is_err());
+    }
+
+    #[test]
+    fn amount_rejects_overflow() {
+        assert!(amount("20000000TiB").is_err());
+        assert!(amount("18446744073709551616").is_err());
+        assert!(age("100000000000000w").is_err());
+        assert_eq!(amount("16000000TiB").unwrap(), 16000000 << 40);
+    }
+
+    #[test]
+    fn metadata_conditions() {
+        let path = temp_file("metadata", &[b'a'; 2000]);
+        let metadata = FileMetadata::read(&path);
+        let at_least = |size: u64, limit: u64| size >= limit;
+        let at_most = |size: u64, limit: u64| size <= limit;
+        let text = |text: &str| Amount::Text(text.to_owned());
+
+        assert!(metadata
+            .check_size(Some(&text("2KB")), at_least)
+            .unwrap()
+            .is_some());
+        assert!(metadata
+            .check_size(Some(&text("2KiB")), at_least)
+            .unwrap()
+            .is_none());
+        assert!(metadata
+            .check_size(Some(&Amount::Number(2000)), at_most)
+            .unwrap()
+            .is_some());
+        assert!(metadata.check_size(None, at_least).unwrap().is_none());
+        assert!(metadata.check_size(Some(&text("2 KBs")), at_least).is_err());
+
+        // The file was only just written.
+        let modified = |m: &Metadata| m.modified();
+        assert!(metadata
+            .check_age(Some(&text("1h")), modified, at_least)
+            .unwrap()
+            .is_none());
+        assert!(metadata
+            .check_age(Some(&text("1h")), modified, at_most)
+            .unwrap()
+            .is_some());
+        assert!(metadata
+            .check_age(Some(&text("1H")), modified, at_most)
+            .is_err());
+
+        let empty = |m: &FileMetadata| Some(m.target.as_ref()?.len() == 0);
+        assert!(metadata.check_flag(Some(false), empty).is_some());
+        assert!(metadata.check_flag(Some(true), empty).is_none());
+        assert!(metadata.check_flag(None, empty).is_none());
+
+        #[cfg(not(windows))]
+        {
+            let hidden = |m: &FileMetadata| Some(m.hidden);
+            assert!(metadata.check_flag(Some(false), hidden).is_some());
+
+            let dotted = path.with_file_name(format!(".fassoc-proxy-{}", std::process::id()));
+            std::fs::write(&dotted, b"").unwrap();
+            let dotted = FileMetadata::read(&dotted);
+            assert!(dotted.check_flag(Some(true), hidden).is_some());
+            assert!(dotted.check_flag(Some(true), empty).is_some());
+        }
+
+        // Nothing matches when the metadata couldn't be read.
+        let missing = FileMetadata::read(&path.with_extension("missing"));
+        assert!(missing
+            .check_size(Some(&Amount::Number(0)), at_least)
+            .unwrap()
+            .is_none());
+        assert!(missing
+            .check_age(Some(&Amount::Number(0)), modified, at_least)
+            .unwrap()
+            .is_none());
+        assert!(missing.check_flag(Some(false), empty).is_none());
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+// ----------------------------------------------------------------------------
+// Variables
+// ----------------------------------------------------------------------------
+
+/// The values that placeholders are substituted with.
+#[derive(Debug, Default, Clone)]
+pub struct Variables {
+    /// The command line arguments received by fassoc-proxy, for `~~$N`.
+    pub arguments: Vec<String>,
+    /// The named variables, for `~~{name}`.
+    pub named: HashMap<String, String>,
+    /// What to do with environment variables that aren't defined.
+    pub undefined: UndefinedVariables,
+}
+
+/// The policy for environment variables in rule strings that aren't defined.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum UndefinedVariables {
+    /// Refuse to substitute the string.
+    Error,
+    /// Substitute the variable with an empty string.
+    Empty,
+    /// Leave the variable as it was written, e.g. `%NAME%`.
+    #[default]
+    Literal,
+}
+
+impl Variables {
+    /// Creates the variables for opening the given file, deriving the named
+    /// variables from its absolute path.
+    pub fn for_file(arguments: Vec<String>, file_path: &Path) -> Variables {
+        let file = std::path::absolute(file_path).unwrap_or_else(|_| file_path.to_path_buf());
+        let dir = file.parent().unwrap_or(Path::new(""));
+
+        let lossy = |value: Option<&OsStr>| {
+            value
+                .map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+
+        let named = HashMap::from([
+            ("file", lossy(Some(file.as_os_str()))),
+            ("dir", lossy(Some(dir.as_os_str()))),
+            ("parent", lossy(dir.file_name())),
+            ("name", lossy(file.file_name())),
+            ("stem", lossy(file.file_stem())),
+            ("ext", lossy(file.extension())),
+            (
+                "proxy",
+                lossy(env::current_exe().ok().as_ref().map(|p| p.as_os_str())),
+            ),
+        ]);
+
+        Variables {
+            arguments,
+            named: named.into_iter().map(|(k, v)| (k.to_owned(), v)).collect(),
+            undefined: UndefinedVariables::default(),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// TemplateError
+// ----------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum TemplateError {
+    /// A `~~${` or `~~{` placeholder that is never closed with a `}`.
+    UnterminatedPlaceholder(String),
+    /// A placeholder whose contents could not be understood.
+    InvalidPlaceholder(String),
+    /// A placeholder referring to an argument index that wasn't supplied.
+    MissingArgument(usize, usize),
+    /// A named placeholder referring to a variable that doesn't exist.
+    UnknownVariable(String),
+    /// An environment variable that isn't defined, when that is an error.
+    UndefinedEnvironmentVariable(String),
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::UnterminatedPlaceholder(template) => {
+                write!(f, "The placeholder in \"{}\" is missing its closing \"}}\"", template)
+            }
+            TemplateError::InvalidPlaceholder(placeholder) => {
+                write!(f, "The placeholder \"{}\" is not valid", placeholder)
+            }
+            TemplateError::MissingArgument(index, count) => write!(
+                f,
+                "The placeholder ~~${{{}}} refers to an argument that wasn't supplied, as only {} arguments were received",
+                index, count
+            ),
+            TemplateError::UnknownVariable(name) => {
+                write!(f, "The placeholder ~~{{{}}} does not refer to a known variable", name)
+            }
+            TemplateError::UndefinedEnvironmentVariable(name) => {
+                write!(f, "The environment variable \"{}\" is not defined", name)
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Template
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Argument(usize),
+    Variable(String),
+    /// An environment variable, along with the text it was written as.
+    Environment(String, String),
+}
+
+/// A parsed rule string, where `~~${N}` (or the shorthand `~~$N`) refers to
+/// the Nth command line argument received by fassoc-proxy, and `~~{name}`
+/// refers to a named variable. `~~$$` and `~~{{` are escaped, literal `~~$`
+/// and `~~{` respectively. Environment variables are expanded when written
+/// as either `%NAME%` or `${NAME}`.
+///
+/// Placeholders are substituted in a single pass, so a substituted argument
+/// that happens to contain a placeholder is never substituted again.
+#[derive(Debug)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut literal = String::new();
+        let mut rest = source;
+
+        while let Some(position) = rest.find(['~', '%', '$']) {
+            literal.push_str(&rest[..position]);
+            rest = &rest[position..];
+
+            match Template::parse_placeholder(source, rest)? {
+                Some((Segment::Literal(escaped), length)) => {
+                    literal.push_str(&escaped);
+                    rest = &rest[length..];
+                }
+
+                Some((segment, length)) => {
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    segments.push(segment);
+                    rest = &rest[length..];
+                }
+
+                // Not a placeholder, only the first character is consumed so
+                // that a placeholder directly after it, e.g. "~~~$1", is seen.
+                None => {
+                    literal.push_str(&rest[..1]);
+                    rest = &rest[1..];
+                }
+            }
+        }
+
+        literal.push_str(rest);
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template { segments })
+    }
+
+    /// Parses the placeholder at the very start of `text`, if there is one,
+    /// returning it along with the length of the text it was written as.
+    fn parse_placeholder(
+        source: &str,
+        text: &str,
+    ) -> Result<Option<(Segment, usize)>, TemplateError> {
+        if let Some(placeholder) = text.strip_prefix("~~$") {
+            if placeholder.starts_with('$') {
+                return Ok(Some((Segment::Literal(String::from("~~$")), 4)));
+            }
+
+            let (index, after) = match placeholder.strip_prefix('{') {
+                Some(braced) => Template::split_braced(source, braced)?,
+                None => placeholder.split_at(
+                    placeholder
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(placeholder.len()),
+                ),
+            };
+
+            let length = text.len() - after.len();
+
+            let index: usize = index
+                .parse()
+                .map_err(|_| TemplateError::InvalidPlaceholder(text[..length].to_owned()))?;
+
+            return Ok(Some((Segment::Argument(index), length)));
+        }
+
+        if let Some(braced) = text.strip_prefix("~~{") {
+            if braced.starts_with('{') {
+                return Ok(Some((Segment::Literal(String::from("~~{")), 4)));
+            }
+
+            let (name, after) = Template::split_braced(source, braced)?;
+
+            if name.is_empty() {
+                return Err(TemplateError::InvalidPlaceholder(String::from("~~{}")));
+            }
+
+            return Ok(Some((
+                Segment::Variable(name.to_owned()),
+                text.len() - after.len(),
+            )));
+        }
+
+        // Environment variables that are malformed, e.g. a lone percent sign,
+        // are left untouched as they are most likely not meant to be one.
+        let environment = if let Some(percent) = text.strip_prefix('%') {
+            percent.find('%').map(|end| (&percent[..end], end + 2))
+        } else if let Some(braced) = text.strip_prefix("${") {
+            braced.find('}').map(|end| (&braced[..end], end + 3))
+        } else {
+            None
+        };
+
+        Ok(environment
+            .filter(|(name, _)| Template::is_environment_name(name))
+            .map(|(name, length)| {
+                (
+                    Segment::Environment(name.to_owned(), text[..length].to_owned()),
+                    length,
+                )
+            }))
+    }
+
+    fn is_environment_name(name: &str) -> bool {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_()".contains(c))
+    }
+
+    /// Splits the contents of a brace delimited placeholder from the rest of
+    /// the string, where `braced` is everything after the opening brace.
+    fn split_braced<'a>(
+        source: &str,
+        braced: &'a str,
+    ) -> Result<(&'a str, &'a str), TemplateError> {
+        let end = braced
+            .find('}')
+            .ok_or_else(|| TemplateError::UnterminatedPlaceholder(source.to_owned()))?;
+
+        Ok((&braced[..end], &braced[end + 1..]))
+    }
+
+    pub fn render(&self, variables: &Variables) -> Result<String, TemplateError> {
+        let mut output = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => output.push_str(literal),
+                Segment::Argument(index) => {
+                    output.push_str(variables.arguments.get(*index).ok_or(
+                        TemplateError::MissingArgument(*index, variables.arguments.len()),
+                    )?)
+                }
+                Segment::Variable(name) => output.push_str(
+                    variables
+                        .named
+                        .get(name)
+                        .ok_or_else(|| TemplateError::UnknownVariable(name.to_owned()))?,
+                ),
+                Segment::Environment(name, written) => match env::var(name) {
+                    Ok(value) => output.push_str(&value),
+                    Err(_) => match variables.undefined {
+                        UndefinedVariables::Error => {
+                            return Err(TemplateError::UndefinedEnvironmentVariable(
+                                name.to_owned(),
+                            ))
+                        }
+                        UndefinedVariables::Empty => (),
+                        UndefinedVariables::Literal => output.push_str(written),
+                    },
+                },
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// Parses and renders the template in one go.
+pub fn substitute(source: &str, variables: &Variables) -> Result<String, TemplateError> {
+    Template::parse(source)?.render(variables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Variables {
+        Variables {
+            arguments: (0..=10).map(|index| format!("arg{}", index)).collect(),
+            named: HashMap::from([(String::from("name"), String::from("file.txt"))]),
+            undefined: UndefinedVariables::default(),
+        }
+    }
+
+    #[test]
+    fn shorthand_index_takes_every_digit() {
+        assert_eq!(substitute("~~$10", &variables()).unwrap(), "arg10");
+        assert_eq!(
+            substitute("~~$1 ~~$10", &variables()).unwrap(),
+            "arg1 arg10"
+        );
+    }
+
+    #[test]
+    fn braced_index_ends_at_brace() {
+        assert_eq!(substitute("~~${1}0", &variables()).unwrap(), "arg10");
+        assert_eq!(substitute("~~${10}", &variables()).unwrap(), "arg10");
+    }
+
+    #[test]
+    fn escapes_are_literal() {
+        assert_eq!(substitute("~~$$1", &variables()).unwrap(), "~~$1");
+        assert_eq!(substitute("~~{{name}", &variables()).unwrap(), "~~{name}");
+        assert_eq!(substitute("~~~$1", &variables()).unwrap(), "~arg1");
+    }
+
+    #[test]
+    fn named_variable() {
+        assert_eq!(
+            substitute("open ~~{name}", &variables()).unwrap(),
+            "open file.txt"
+        );
+        assert!(matches!(
+            substitute("~~{missing}", &variables()),
+            Err(TemplateError::UnknownVariable(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn missing_index() {
+        assert!(matches!(
+            substitute("~~$11", &variables()),
+            Err(TemplateError::MissingArgument(11, 11))
+        ));
+        assert!(matches!(
+            substitute("~~${x}", &variables()),
+            Err(TemplateError::InvalidPlaceholder(placeholder)) if placeholder == "~~${x}"
+        ));
+    }
+
+    #[test]
+    fn unterminated_brace() {
+        assert!(matches!(
+            substitute("~~${1", &variables()),
+            Err(TemplateError::UnterminatedPlaceholder(_))
+        ));
+        assert!(matches!(
+            substitute("a ~~{name", &variables()),
+            Err(TemplateError::UnterminatedPlaceholder(_))
+        ));
+    }
+
+    #[test]
+    fn substituted_arguments_are_not_substituted_again() {
+        let mut variables = variables();
+        variables.arguments[1] = String::from("~~$2 %PATH%");
+
+        assert_eq!(substitute("~~$1", &variables).unwrap(), "~~$2 %PATH%");
+    }
+}
@@ -0,0 +1,840 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use globset as gs;
+use serde_json as sj;
+
+use super::document::{Node, NodeKind, Position};
+use super::rules::{Amount, PatternError, Signature, AGE_UNITS, SIZE_UNITS};
+
+// ----------------------------------------------------------------------------
+// Schema
+// ----------------------------------------------------------------------------
+
+// The fields of every object in the rules file, which have to be kept in sync
+// with the structs in rules.rs that they are deserialized into.
+
+const RULES_FIELDS: &[&str] = &[
+    "include", "options", "mappings", "matchers", "commands", "on_error",
+];
+
+const OPTIONS_FIELDS: &[&str] = &["undefined_variables", "max_depth", "content_limit"];
+
+const MATCHER_FIELDS: &[&str] = &[
+    "command",
+    "case_sensitive",
+    "content_limit",
+    "content_stream",
+];
+
+const CONDITION_FIELDS: &[&str] = &[
+    "regexf",
+    "glob",
+    "regexp",
+    "regexd",
+    "globp",
+    "globd",
+    "under",
+    "project",
+    "min_size",
+    "max_size",
+    "min_modified_age",
+    "max_modified_age",
+    "min_created_age",
+    "max_created_age",
+    "empty",
+    "readonly",
+    "hidden",
+    "symlink",
+    "magic",
+    "interpreter",
+    "regexc",
+    "all",
+    "any",
+    "not",
+];
+
+const PROJECT_FIELDS: &[&str] = &["markers", "max_depth"];
+
+const SIGNATURE_FIELDS: &[&str] = &["bytes", "offset"];
+
+const COMMAND_FIELDS: &[&str] = &[
+    "path",
+    "arguments",
+    "cwd",
+    "process_attributes",
+    "thread_attributes",
+    "inherit_handles",
+    "creation_flags",
+    "environment",
+    "extras",
+];
+
+const SECURITY_ATTRIBUTES_FIELDS: &[&str] = &["security_descriptor", "inherit_handle"];
+
+const ENVIRONMENT_FIELDS: &[&str] = &["clear", "set", "unset", "prepend", "append"];
+
+const EXTRAS_FIELDS: &[&str] = &[
+    "desktop",
+    "title",
+    "x",
+    "y",
+    "x_size",
+    "y_size",
+    "x_count_chars",
+    "y_count_chars",
+    "fill_attribute",
+    "flags",
+    "show_window",
+];
+
+// The names of the flags that can be given instead of numbers, which have to
+// be kept in sync with the resolvers in winproc.rs.
+
+const CREATION_FLAGS: &[&str] = &[
+    "CREATE_BREAKAWAY_FROM_JOB",
+    "CREATE_DEFAULT_ERROR_MODE",
+    "CREATE_NEW_CONSOLE",
+    "CREATE_NEW_PROCESS_GROUP",
+    "CREATE_NO_WINDOW",
+    "CREATE_PROTECTED_PROCESS",
+    "CREATE_PRESERVE_CODE_AUTHZ_LEVEL",
+    "CREATE_SECURE_PROCESS",
+    "CREATE_SEPARATE_WOW_VDM",
+    "CREATE_SHARED_WOW_VDM",
+    "CREATE_SUSPENDED",
+    "CREATE_UNICODE_ENVIRONMENT",
+    "DEBUG_ONLY_THIS_PROCESS",
+    "DEBUG_PROCESS",
+    "DETACHED_PROCESS",
+    "EXTENDED_STARTUPINFO_PRESENT",
+    "INHERIT_PARENT_AFFINITY",
+];
+
+const FILL_ATTRIBUTES: &[&str] = &[
+    "FOREGROUND_BLUE",
+    "FOREGROUND_RED",
+    "FOREGROUND_GREEN",
+    "BACKGROUND_BLUE",
+    "BACKGROUND_RED",
+    "BACKGROUND_GREEN",
+    "BACKGROUND_INTENSITY",
+    "FOREGROUND_INTENSITY",
+    "COMMON_LVB_LEADING_BYTE",
+    "COMMON_LVB_TRAILING_BYTE",
+    "COMMON_LVB_GRID_HORIZONTAL",
+    "COMMON_LVB_GRID_LVERTICAL",
+    "COMMON_LVB_GRID_RVERTICAL",
+    "COMMON_LVB_REVERSE_VIDEO",
+    "COMMON_LVB_UNDERSCORE",
+    "COMMON_LVB_SBCSDBCS",
+];
+
+const STARTUP_FLAGS: &[&str] = &[
+    "STARTF_FORCEONFEEDBACK",
+    "STARTF_FORCEOFFFEEDBACK",
+    "STARTF_PREVENTPINNING",
+    "STARTF_RUNFULLSCREEN",
+    "STARTF_TITLEISAPPID",
+    "STARTF_TITLEISLINKNAME",
+    "STARTF_UNTRUSTEDSOURCE",
+    "STARTF_USECOUNTCHARS",
+    "STARTF_USEFILLATTRIBUTE",
+    "STARTF_USEHOTKEY",
+    "STARTF_USEPOSITION",
+    "STARTF_USESHOWWINDOW",
+    "STARTF_USESIZE",
+    "STARTF_USESTDHANDLES",
+];
+
+const SHOW_WINDOW_COMMANDS: &[&str] = &[
+    "SW_HIDE",
+    "SW_MAXIMIZE",
+    "SW_MINIMIZE",
+    "SW_RESTORE",
+    "SW_SHOW",
+    "SW_SHOWDEFAULT",
+    "SW_SHOWMAXIMIZED",
+    "SW_SHOWMINIMIZED",
+    "SW_SHOWMINNOACTIVE",
+    "SW_SHOWNA",
+    "SW_SHOWNOACTIVATE",
+    "SW_SHOWNORMAL",
+];
+
+// ----------------------------------------------------------------------------
+// Diagnostic
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The rules file is rejected.
+    Error,
+    /// The rules file is accepted, but likely doesn't do what was intended.
+    Warning,
+    /// The rules file is accepted, and likely does what was intended, which is
+    /// only worth mentioning when validating the rules file on its own.
+    Note,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The included rules file the diagnostic is about, if it isn't about the
+    /// rules file that fassoc-proxy was given.
+    pub file: Option<Rc<str>>,
+    pub position: Option<Position>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+
+        write!(f, "{}", severity)?;
+
+        if let Some(file) = &self.file {
+            write!(f, " in \"{}\"", file)?;
+        }
+
+        if let Some(position) = self.position {
+            write!(f, " at line {}, column {}", position.line, position.column)?;
+        }
+
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Checks the rules for mistakes that deserializing them doesn't catch, i.e.
+/// unknown fields, unknown flag names, invalid amounts, globs and signatures,
+/// references to matchers and commands that don't exist, and matchers that
+/// are unreachable. The rules are checked after any included rules files have
+/// been merged into them. The RegEx patterns that failed to compile are
+/// reported along with the rest.
+pub fn validate(root: &Node, pattern_errors: Vec<PatternError>) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        matchers: root.field("matchers").map(Node::keys).unwrap_or_default(),
+        commands: root.field("commands").map(Node::keys).unwrap_or_default(),
+        diagnostics: Vec::new(),
+    };
+
+    validator.check_rules(root);
+    validator.check_patterns(root, pattern_errors);
+    validator
+        .diagnostics
+        .sort_by_key(|diagnostic| (diagnostic.file.clone(), diagnostic.position));
+    validator.diagnostics
+}
+
+// ----------------------------------------------------------------------------
+// Validator
+// ----------------------------------------------------------------------------
+
+struct Validator<'a> {
+    matchers: HashSet<&'a str>,
+    commands: HashSet<&'a str>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(
+        &mut self,
+        severity: Severity,
+        file: &Option<Rc<str>>,
+        position: Option<Position>,
+        message: String,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            file: file.clone(),
+            position,
+            message,
+        });
+    }
+
+    /// Reports every field of the object that isn't in any of the lists of
+    /// known fields, where `location` describes the object.
+    fn check_fields(&mut self, node: &Node, known: &[&[&str]], location: &str) {
+        for entry in node.entries() {
+            if known
+                .iter()
+                .any(|fields| fields.contains(&entry.key.as_str()))
+            {
+                continue;
+            }
+
+            let suggestion = suggest(known.iter().flat_map(|fields| fields.iter()), &entry.key);
+
+            self.report(
+                Severity::Error,
+                &entry.value.file,
+                entry.position,
+                format!(
+                    "unknown field \"{}\" in {}{}",
+                    entry.key, location, suggestion
+                ),
+            );
+        }
+    }
+
+    /// Reports every flag name in the list that isn't one of the known names.
+    /// Numbers are passed through as they are, so they are always valid.
+    fn check_flags(&mut self, node: Option<&Node>, known: &[&str], location: &str) {
+        for item in node.map(Node::items).unwrap_or_default() {
+            if let NodeKind::String(name) = &item.kind {
+                if !known.contains(&name.as_str()) {
+                    self.report(
+                        Severity::Error,
+                        &item.file,
+                        item.position,
+                        format!(
+                            "unknown flag \"{}\" in {}{}",
+                            name,
+                            location,
+                            suggest(known.iter(), name)
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_rules(&mut self, root: &'a Node) {
+        self.check_fields(root, &[RULES_FIELDS], "the rules");
+
+        if let Some(options) = root.field("options") {
+            self.check_fields(options, &[OPTIONS_FIELDS], "options");
+            self.check_amount(
+                options.field("content_limit"),
+                &SIZE_UNITS,
+                "the content_limit of the options",
+            );
+        }
+
+        let mut referenced: HashSet<&str> = HashSet::new();
+
+        // The candidates that match every file, which are commands that are
+        // referred to directly, and matchers without any conditions.
+        let unconditional: HashSet<&str> = root
+            .field("matchers")
+            .map(Node::entries)
+            .unwrap_or_default()
+            .iter()
+            .filter(|matcher| {
+                !matcher
+                    .value
+                    .keys()
+                    .iter()
+                    .any(|key| CONDITION_FIELDS.contains(key))
+            })
+            .map(|matcher| matcher.key.as_str())
+            .chain(self.commands.difference(&self.matchers).copied())
+            .collect();
+
+        for mapping in root
+            .field("mappings")
+            .map(Node::entries)
+            .unwrap_or_default()
+        {
+            // The first candidate that always matches, and the candidates after
+            // it, which are only tried if it fails to launch.
+            let mut shadowing: Option<(&Node, &str)> = None;
+            let mut shadowed: Vec<String> = Vec::new();
+
+            for item in mapping.value.items() {
+                if let NodeKind::String(name) = &item.kind {
+                    referenced.insert(name);
+
+                    if !self.matchers.contains(name.as_str())
+                        && !self.commands.contains(name.as_str())
+                    {
+                        self.report(
+                            Severity::Error,
+                            &item.file,
+                            item.position,
+                            format!(
+                                "the mapping \"{}\" refers to \"{}\", which is neither a matcher nor a command",
+                                mapping.key, name
+                            ),
+                        );
+                    }
+
+                    if shadowing.is_some() {
+                        shadowed.push(format!("\"{}\"", name));
+                    } else if unconditional.contains(name.as_str()) {
+                        shadowing = Some((item, name));
+                    }
+                }
+            }
+
+            // This is often intended, as a chain of fallbacks for when a command
+            // fails to launch, so it is only a note.
+            if let (Some((item, name)), false) = (shadowing, shadowed.is_empty()) {
+                self.report(
+                    Severity::Note,
+                    &item.file,
+                    item.position,
+                    format!(
+                        "\"{}\" in the mapping \"{}\" matches every file, so {} after it is only tried if it fails to launch",
+                        name,
+                        mapping.key,
+                        shadowed.join(", ")
+                    ),
+                );
+            }
+        }
+
+        for matcher in root
+            .field("matchers")
+            .map(Node::entries)
+            .unwrap_or_default()
+        {
+            let location = format!("the matcher \"{}\"", matcher.key);
+            self.check_fields(
+                &matcher.value,
+                &[MATCHER_FIELDS, CONDITION_FIELDS],
+                &location,
+            );
+            self.check_condition(&matcher.value, &location);
+            self.check_amount(
+                matcher.value.field("content_limit"),
+                &SIZE_UNITS,
+                &format!("the content_limit of {}", location),
+            );
+
+            if let Some(command) = matcher.value.field("command") {
+                if let NodeKind::String(name) = &command.kind {
+                    if !self.commands.contains(name.as_str()) {
+                        self.report(
+                            Severity::Error,
+                            &command.file,
+                            command.position,
+                            format!(
+                                "{} refers to the command \"{}\", which does not exist",
+                                location, name
+                            ),
+                        );
+                    }
+                }
+            }
+
+            if !referenced.contains(matcher.key.as_str()) {
+                self.report(
+                    Severity::Warning,
+                    &matcher.value.file,
+                    matcher.position,
+                    format!(
+                        "{} is unreachable, because no mapping refers to it",
+                        location
+                    ),
+                );
+            }
+        }
+
+        for command in root
+            .field("commands")
+            .map(Node::entries)
+            .unwrap_or_default()
+        {
+            self.check_command(&command.value, &format!("the command \"{}\"", command.key));
+        }
+
+        if let Some(on_error) = root.field("on_error") {
+            if let NodeKind::String(name) = &on_error.kind {
+                if !self.commands.contains(name.as_str()) {
+                    self.report(
+                        Severity::Error,
+                        &on_error.file,
+                        on_error.position,
+                        format!(
+                            "on_error refers to the command \"{}\", which does not exist",
+                            name
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Checks the objects within the conditions, including the groups, whose
+    /// own fields have already been checked.
+    fn check_condition(&mut self, node: &Node, location: &str) {
+        if let Some(project) = node.field("project") {
+            self.check_fields(
+                project,
+                &[PROJECT_FIELDS],
+                &format!("the project of {}", location),
+            );
+        }
+
+        for field in ["glob", "globp", "globd", "interpreter"] {
+            for glob in node.field(field).map(one_or_many).unwrap_or_default() {
+                if let NodeKind::String(pattern) = &glob.kind {
+                    if let Err(error) = gs::Glob::new(pattern) {
+                        self.report(
+                            Severity::Error,
+                            &glob.file,
+                            glob.position,
+                            format!("the {} of {} is invalid: {}", field, location, error),
+                        );
+                    }
+                }
+            }
+        }
+
+        for (field, units) in [
+            ("min_size", &SIZE_UNITS[..]),
+            ("max_size", &SIZE_UNITS[..]),
+            ("min_modified_age", &AGE_UNITS[..]),
+            ("max_modified_age", &AGE_UNITS[..]),
+            ("min_created_age", &AGE_UNITS[..]),
+            ("max_created_age", &AGE_UNITS[..]),
+        ] {
+            self.check_amount(
+                node.field(field),
+                units,
+                &format!("the {} of {}", field, location),
+            );
+        }
+
+        if let Some(magic) = node.field("magic") {
+            let location = format!("the magic of {}", location);
+
+            for signature in one_or_many(magic) {
+                self.check_fields(signature, &[SIGNATURE_FIELDS], &location);
+
+                // Signatures of the wrong type have already been rejected when
+                // the rules were deserialized.
+                let patterns = sj::from_value::<Signature>(signature.to_value())
+                    .map(|signature| signature.patterns());
+
+                if let Ok(Err(error)) = patterns {
+                    self.report(
+                        Severity::Error,
+                        &signature.file,
+                        signature.position,
+                        format!("{} is invalid: {}", location, error),
+                    );
+                }
+            }
+        }
+
+        for group in ["all", "any"] {
+            for (index, condition) in node
+                .field(group)
+                .map(Node::items)
+                .unwrap_or_default()
+                .iter()
+                .enumerate()
+            {
+                let location = format!("the \"{}[{}]\" group of {}", group, index, location);
+                self.check_fields(condition, &[CONDITION_FIELDS], &location);
+                self.check_condition(condition, &location);
+            }
+        }
+
+        if let Some(condition) = node.field("not") {
+            let location = format!("the \"not\" group of {}", location);
+            self.check_fields(condition, &[CONDITION_FIELDS], &location);
+            self.check_condition(condition, &location);
+        }
+    }
+
+    /// Reports the amount if its unit isn't one of the units, or its number
+    /// can't be parsed, where `location` describes the amount.
+    fn check_amount(&mut self, node: Option<&Node>, units: &[(&str, u64)], location: &str) {
+        let node = match node {
+            Some(node) => node,
+            None => return,
+        };
+
+        if let Ok(Err(error)) =
+            sj::from_value::<Amount>(node.to_value()).map(|amount| amount.value(units))
+        {
+            self.report(
+                Severity::Error,
+                &node.file,
+                node.position,
+                format!("{} is invalid: {}", location, error),
+            );
+        }
+    }
+
+    /// Reports the RegEx patterns that failed to compile, at the position of
+    /// the pattern, or of the matcher if the pattern can't be found.
+    fn check_patterns(&mut self, root: &Node, pattern_errors: Vec<PatternError>) {
+        for pattern_error in pattern_errors {
+            let matcher = root
+                .field("matchers")
+                .and_then(|matchers| matchers.field(&pattern_error.matcher));
+            let node = matcher
+                .and_then(|matcher| find_condition(matcher, &pattern_error.condition))
+                .or(matcher);
+
+            self.report(
+                Severity::Error,
+                &node.and_then(|node| node.file.clone()),
+                node.and_then(|node| node.position),
+                format!(
+                    "the \"{}\" pattern of the matcher \"{}\" is invalid: {}",
+                    pattern_error.condition, pattern_error.matcher, pattern_error.error
+                ),
+            );
+        }
+    }
+
+    fn check_command(&mut self, node: &Node, location: &str) {
+        self.check_fields(node, &[COMMAND_FIELDS], location);
+
+        for field in ["process_attributes", "thread_attributes"] {
+            if let Some(attributes) = node.field(field) {
+                self.check_fields(
+                    attributes,
+                    &[SECURITY_ATTRIBUTES_FIELDS],
+                    &format!("the {} of {}", field, location),
+                );
+            }
+        }
+
+        if let Some(environment) = node.field("environment") {
+            self.check_fields(
+                environment,
+                &[ENVIRONMENT_FIELDS],
+                &format!("the environment of {}", location),
+            );
+        }
+
+        self.check_flags(
+            node.field("creation_flags"),
+            CREATION_FLAGS,
+            &format!("the creation_flags of {}", location),
+        );
+
+        if let Some(extras) = node.field("extras") {
+            self.check_fields(
+                extras,
+                &[EXTRAS_FIELDS],
+                &format!("the extras of {}", location),
+            );
+
+            for (field, known) in [
+                ("fill_attribute", FILL_ATTRIBUTES),
+                ("flags", STARTUP_FLAGS),
+                ("show_window", SHOW_WINDOW_COMMANDS),
+            ] {
+                self.check_flags(
+                    extras.field(field),
+                    known,
+                    &format!("the {} of {}", field, location),
+                );
+            }
+        }
+    }
+}
+
+/// The items of a field that can be either a single value or a list of them.
+fn one_or_many(node: &Node) -> Vec<&Node> {
+    match &node.kind {
+        NodeKind::Array(items) => items.iter().collect(),
+        _ => vec![node],
+    }
+}
+
+/// Finds a condition within a matcher by its path, e.g. "any[1].regexc".
+fn find_condition<'n>(matcher: &'n Node, path: &str) -> Option<&'n Node> {
+    path.split('.').try_fold(matcher, |node, segment| {
+        match segment
+            .strip_suffix(']')
+            .and_then(|segment| segment.split_once('['))
+        {
+            Some((group, index)) => node.field(group)?.items().get(index.parse::<usize>().ok()?),
+            None => node.field(segment),
+        }
+    })
+}
+
+/// A hint naming the known name that the unknown one is most likely a
+/// misspelling of, if there is one.
+fn suggest<'n>(mut known: impl Iterator<Item = &'n &'n str>, unknown: &str) -> String {
+    known
+        .find(|name| is_similar(name, unknown))
+        .map(|name| format!(", did you mean \"{}\"?", name))
+        .unwrap_or_default()
+}
+
+/// Whether an unknown name is likely a misspelling of a known one, i.e. it
+/// only differs in case, or by at most two edits.
+fn is_similar(known: &str, unknown: &str) -> bool {
+    if known.eq_ignore_ascii_case(unknown) {
+        return true;
+    }
+
+    let known: Vec<char> = known.chars().collect();
+    let unknown: Vec<char> = unknown.chars().collect();
+
+    // The Levenshtein distance, computed one row at a time.
+    let mut previous: Vec<usize> = (0..=unknown.len()).collect();
+
+    for (i, k) in known.iter().enumerate() {
+        let mut current: Vec<usize> = vec![i + 1];
+
+        for (j, u) in unknown.iter().enumerate() {
+            let substitution = previous[j] + usize::from(k != u);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[unknown.len()] <= 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostics(source: &str) -> Vec<Diagnostic> {
+        validate(&Node::parse(source, None).unwrap(), Vec::new())
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        diagnostics(source)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    const COMMANDS: &str = r#""commands": { "C": { "path": "/bin/true" } }"#;
+
+    #[test]
+    fn valid_rules_have_no_diagnostics() {
+        let source = format!(
+            r#"{{ "mappings": {{ "txt": ["M", "C"] }}, "matchers": {{ "M": {{ "command": "C", "glob": "*.md", "min_size": "5 MiB" }} }}, {} }}"#,
+            COMMANDS
+        );
+
+        assert!(messages(&source).is_empty());
+    }
+
+    #[test]
+    fn unknown_fields_with_suggestions() {
+        let source = format!(
+            "{{\n  \"mappings\": {{ \"txt\": [\"M\"] }},\n  \"matchers\": {{ \"M\": {{ \"command\": \"C\", \"regexF\": \"a\", \"any\": [ {{ \"glod\": \"b\" }} ] }} }},\n  {},\n  \"extra\": 1\n}}",
+            COMMANDS
+        );
+
+        assert_eq!(
+            messages(&source),
+            [
+                "error at line 3, column 40: unknown field \"regexF\" in the matcher \"M\", did you mean \"regexf\"?",
+                "error at line 3, column 66: unknown field \"glod\" in the \"any[0]\" group of the matcher \"M\", did you mean \"glob\"?",
+                "error at line 5, column 3: unknown field \"extra\" in the rules",
+            ]
+        );
+    }
+
+    #[test]
+    fn dangling_references() {
+        let source = r#"{ "mappings": { "txt": ["Ghost"] }, "matchers": { "M": { "command": "Nope" } }, "on_error": "Gone" }"#;
+
+        assert_eq!(
+            messages(source),
+            [
+                "error at line 1, column 25: the mapping \"txt\" refers to \"Ghost\", which is neither a matcher nor a command",
+                "warning at line 1, column 51: the matcher \"M\" is unreachable, because no mapping refers to it",
+                "error at line 1, column 69: the matcher \"M\" refers to the command \"Nope\", which does not exist",
+                "error at line 1, column 93: on_error refers to the command \"Gone\", which does not exist",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_flags() {
+        let source = r#"{ "commands": { "C": { "path": "p", "creation_flags": ["CREATE_NEW_CONSOLE", 16, "CREATE_NEW_CONSOLES"], "extras": { "show_window": ["SW_BOGUS"] } } } }"#;
+
+        assert_eq!(
+            messages(source),
+            [
+                "error at line 1, column 82: unknown flag \"CREATE_NEW_CONSOLES\" in the creation_flags of the command \"C\", did you mean \"CREATE_NEW_CONSOLE\"?",
+                "error at line 1, column 134: unknown flag \"SW_BOGUS\" in the show_window of the command \"C\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_condition_values() {
+        let source = format!(
+            r#"{{ "mappings": {{ "txt": ["M"] }}, "matchers": {{ "M": {{ "command": "C", "min_size": "50 MBs", "glob": "[", "not": {{ "magic": {{ "bytes": "00", "offset": 2000000 }} }} }} }}, {} }}"#,
+            COMMANDS
+        );
+
+        let messages = messages(&source);
+        assert_eq!(messages.len(), 3);
+        assert!(
+            messages[0].contains("the min_size of the matcher \"M\" is invalid: Invalid Amount")
+        );
+        assert!(messages[1].contains("the glob of the matcher \"M\" is invalid"));
+        assert!(messages[2].contains(
+            "the magic of the \"not\" group of the matcher \"M\" is invalid: Invalid Offset"
+        ));
+    }
+
+    #[test]
+    fn pattern_errors_are_located() {
+        let root = Node::parse(
+            r#"{ "matchers": { "M": { "command": "C", "any": [ {}, { "regexc": "(" } ] } } }"#,
+            None,
+        )
+        .unwrap();
+
+        // The pattern is only known at runtime in practice.
+        let pattern = String::from("(");
+
+        let pattern_error = PatternError {
+            matcher: String::from("M"),
+            condition: String::from("any[1].regexc"),
+            error: regex::Regex::new(&pattern).unwrap_err(),
+        };
+
+        let diagnostic = validate(&root, vec![pattern_error])
+            .into_iter()
+            .find(|diagnostic| {
+                diagnostic
+                    .message
+                    .starts_with("the \"any[1].regexc\" pattern of the matcher \"M\" is invalid")
+            })
+            .unwrap();
+
+        assert_eq!(
+            diagnostic.position,
+            Some(Position {
+                line: 1,
+                column: 65
+            })
+        );
+    }
+
+    #[test]
+    fn shadowed_entries_are_a_note_at_the_shadowing_entry() {
+        let source = format!(
+            r#"{{ "mappings": {{ "txt": ["M", "C", "N", "M"] }}, "matchers": {{ "M": {{ "command": "C", "glob": "a" }}, "N": {{ "command": "C" }} }}, {} }}"#,
+            COMMANDS
+        );
+
+        assert_eq!(
+            messages(&source),
+            ["note at line 1, column 30: \"C\" in the mapping \"txt\" matches every file, so \"N\", \"M\" after it is only tried if it fails to launch"]
+        );
+    }
+
+    #[test]
+    fn similar_names() {
+        assert!(is_similar("regexf", "REGEXF"));
+        assert!(is_similar("arguments", "argments"));
+        assert!(!is_similar("glob", "under"));
+    }
+}
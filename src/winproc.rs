@@ -1,4 +1,5 @@
 use std::ffi::{c_void, CString};
+use std::os::windows::ffi::OsStrExt;
 use windows::core::{PCSTR, PSTR};
 use windows::Win32::System::Threading::{CreateProcessA, PROCESS_INFORMATION};
 
@@ -8,19 +9,20 @@ use windows::Win32::UI::WindowsAndMessaging::{
     SW_SHOWNORMAL,
 };
 
+use super::launcher::{check_command_path, CreateProcessError, Launcher};
 use super::rules::Command;
 use std::collections::HashMap;
 use std::mem::size_of;
 use std::ptr;
-use windows::Win32::Foundation::BOOL;
+use windows::Win32::Foundation::{GetLastError, BOOL};
 use windows::Win32::Security::SECURITY_ATTRIBUTES;
 
 use windows::Win32::System::Console::{
-    BACKGROUND_BLUE, BACKGROUND_GREEN, BACKGROUND_INTENSITY, BACKGROUND_RED,
-    COMMON_LVB_GRID_HORIZONTAL, COMMON_LVB_GRID_LVERTICAL, COMMON_LVB_GRID_RVERTICAL,
-    COMMON_LVB_LEADING_BYTE, COMMON_LVB_REVERSE_VIDEO, COMMON_LVB_SBCSDBCS,
-    COMMON_LVB_TRAILING_BYTE, COMMON_LVB_UNDERSCORE, FOREGROUND_BLUE, FOREGROUND_GREEN,
-    FOREGROUND_INTENSITY, FOREGROUND_RED,
+    AttachConsole, ATTACH_PARENT_PROCESS, BACKGROUND_BLUE, BACKGROUND_GREEN, BACKGROUND_INTENSITY,
+    BACKGROUND_RED, COMMON_LVB_GRID_HORIZONTAL, COMMON_LVB_GRID_LVERTICAL,
+    COMMON_LVB_GRID_RVERTICAL, COMMON_LVB_LEADING_BYTE, COMMON_LVB_REVERSE_VIDEO,
+    COMMON_LVB_SBCSDBCS, COMMON_LVB_TRAILING_BYTE, COMMON_LVB_UNDERSCORE, FOREGROUND_BLUE,
+    FOREGROUND_GREEN, FOREGROUND_INTENSITY, FOREGROUND_RED,
 };
 
 use windows::Win32::System::Threading::{
@@ -45,6 +47,8 @@ pub struct ProcessCreationParameters {
     pub inherit_handles: BOOL,
     pub creation_flags: PROCESS_CREATION_FLAGS,
     pub environment: *const c_void,
+    /// The UTF-16 environment block that `environment` points into, if any.
+    pub environment_block: Vec<u16>,
     pub current_directory: PCSTR,
     pub startup_info: STARTUPINFOA,
 }
@@ -60,6 +64,7 @@ impl Default for ProcessCreationParameters {
             inherit_handles: BOOL(0),
             creation_flags: PROCESS_CREATION_FLAGS::default(),
             environment: ptr::null(),
+            environment_block: Vec::new(),
             current_directory: PCSTR::null(),
             startup_info: STARTUPINFOA {
                 cb: size_of::<STARTUPINFOA>() as u32,
@@ -70,42 +75,24 @@ impl Default for ProcessCreationParameters {
 }
 
 impl ProcessCreationParameters {
-    pub fn from_rule(rule: &Command) -> ProcessCreationParameters {
+    pub fn from_rule(rule: &Command) -> Result<ProcessCreationParameters, CreateProcessError> {
         let mut pcp = ProcessCreationParameters::default();
 
-        pcp.command = CString::new(rule.path.to_owned()).map_or_else(
-            |error| {
-                log::error!("Couldn't nativize the command string \"{}\" from the selected rule, due to error: {}", 
-                    rule.path, error);
+        pcp.command = CString::new(rule.path.to_owned())
+            .map(|cstr| PCSTR(cstr.into_raw() as *mut u8))
+            .map_err(|_| CreateProcessError::StringContainsNul(rule.path.to_owned()))?;
 
-                panic!();
-            },
-            |cstr| PCSTR(cstr.into_raw() as *mut u8),
-        );
-
-        rule.arguments.to_owned().map(|args| {
-            pcp.arguments = CString::new(args.to_owned()).map_or_else(
-                |error| {
-                    log::error!("Couldn't nativize the arguments string \"{}\" from the selected rule, due to error: {}", 
-                        args, error);
-
-                    panic!();
-                },
-                |cstr| PSTR(cstr.into_raw() as *mut u8),
-            );
-        });
-
-        rule.cwd.to_owned().map(|cwd| {
-            pcp.cwd = CString::new(cwd.to_owned()).map_or_else(
-                |error| {
-                    log::error!("Couldn't nativize the cwd string \"{}\" from the selected rule, due to error: {}", 
-                        cwd, error);
+        if let Some(args) = &rule.arguments {
+            pcp.arguments = CString::new(args.to_owned())
+                .map(|cstr| PSTR(cstr.into_raw() as *mut u8))
+                .map_err(|_| CreateProcessError::StringContainsNul(args.to_owned()))?;
+        }
 
-                    panic!();
-                },
-                |cstr| PCSTR(cstr.into_raw() as *mut u8),
-            );
-        });
+        if let Some(cwd) = &rule.cwd {
+            pcp.cwd = CString::new(cwd.to_owned())
+                .map(|cstr| PCSTR(cstr.into_raw() as *mut u8))
+                .map_err(|_| CreateProcessError::StringContainsNul(cwd.to_owned()))?;
+        }
 
         pcp.thread_attributes = SECURITY_ATTRIBUTES::default();
 
@@ -182,6 +169,33 @@ impl ProcessCreationParameters {
             pcp.creation_flags = PROCESS_CREATION_FLAGS(flags);
         });
 
+        if let Some(environment) = &rule.environment {
+            let mut variables = environment.resolve();
+
+            // Windows expects the block to be sorted by name, case insensitively.
+            variables.sort_by_key(|(name, _)| name.to_string_lossy().to_uppercase());
+
+            pcp.environment_block = variables
+                .iter()
+                .flat_map(|(name, value)| {
+                    name.encode_wide()
+                        .chain("=".encode_utf16())
+                        .chain(value.encode_wide())
+                        .chain([0])
+                })
+                .collect();
+
+            // The block is terminated by an additional null, and an empty block
+            // still has to consist of two.
+            if pcp.environment_block.is_empty() {
+                pcp.environment_block.push(0);
+            }
+
+            pcp.environment_block.push(0);
+            pcp.environment = pcp.environment_block.as_ptr() as *const c_void;
+            pcp.creation_flags |= CREATE_UNICODE_ENVIRONMENT;
+        }
+
         rule.extras.to_owned().map(|extras| {
             let mut native = STARTUPINFOA {
                 cb: size_of::<STARTUPINFOA>() as u32,
@@ -220,7 +234,7 @@ impl ProcessCreationParameters {
                     ("BACKGROUND_INTENSITY", BACKGROUND_INTENSITY),
                     ("FOREGROUND_INTENSITY", FOREGROUND_INTENSITY),
                     ("COMMON_LVB_LEADING_BYTE", COMMON_LVB_LEADING_BYTE),
-                    ("COMMON_LVB_TRAILING_BYT", COMMON_LVB_TRAILING_BYTE),
+                    ("COMMON_LVB_TRAILING_BYTE", COMMON_LVB_TRAILING_BYTE),
                     ("COMMON_LVB_GRID_HORIZONTAL", COMMON_LVB_GRID_HORIZONTAL),
                     ("COMMON_LVB_GRID_LVERTICAL", COMMON_LVB_GRID_LVERTICAL),
                     ("COMMON_LVB_GRID_RVERTICAL", COMMON_LVB_GRID_RVERTICAL),
@@ -297,77 +311,62 @@ impl ProcessCreationParameters {
             });
         });
 
-        return pcp;
+        Ok(pcp)
     }
 }
 
-#[derive(Debug)]
-pub enum CreateProcessError {
-    CommandNotExecutable(String),
-    CommandDoesNotExist(String),
-    CommandNotAbsolute(String),
-}
-
-impl std::fmt::Display for CreateProcessError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            CreateProcessError::CommandNotExecutable(cmd) => {
-                write!(f, "The command \"{}\" is not executable", cmd)
-            }
-            CreateProcessError::CommandDoesNotExist(cmd) => {
-                write!(f, "The command \"{}\" does not exist", cmd)
-            }
-            CreateProcessError::CommandNotAbsolute(cmd) => {
-                write!(f, "The commaned \"{}\" does not have an absolute path", cmd)
-            }
-        }
-    }
-}
-
-pub fn invoke_command(rule: &Command) -> Result<PROCESS_INFORMATION, CreateProcessError> {
-    let params = ProcessCreationParameters::from_rule(rule);
+/// Launches commands through `CreateProcessA`, mapping every field of the
+/// `Command` onto its WinAPI equivalent.
+#[derive(Debug, Default)]
+pub struct WinApiLauncher;
+
+impl Launcher for WinApiLauncher {
+    type Process = PROCESS_INFORMATION;
+
+    fn launch(&self, rule: &Command) -> Result<PROCESS_INFORMATION, CreateProcessError> {
+        let params = ProcessCreationParameters::from_rule(rule)?;
+
+        check_command_path(&rule.path)?;
+
+        unsafe {
+            let mut process_information = PROCESS_INFORMATION::default();
+
+            let result = CreateProcessA(
+                params.command,
+                params.arguments,
+                &params.process_attributes,
+                &params.thread_attributes,
+                params.inherit_handles,
+                params.creation_flags,
+                params.environment,
+                params.cwd,
+                &params.startup_info,
+                &mut process_information,
+            );
 
-    let command_path = std::path::Path::new(&rule.path);
+            log::debug!("CreateProcessA returned: {:?}", result);
 
-    if !command_path.exists() {
-        return Err(CreateProcessError::CommandDoesNotExist(rule.path.clone()));
-    }
+            if result.0 == 0 {
+                return Err(CreateProcessError::CreationFailed(
+                    rule.path.to_owned(),
+                    format!("CreateProcessA failed with error code {}", GetLastError().0),
+                ));
+            }
 
-    if !command_path.extension().map_or(false, |ext| {
-        ext.to_str().map_or(false, |exts| exts.ends_with("exe"))
-    }) {
-        return Err(CreateProcessError::CommandNotExecutable(
-            rule.path.to_owned(),
-        ));
-    }
+            log::debug!("Process Information ---------\n{:?}", process_information);
 
-    if !command_path.is_absolute() {
-        return Err(CreateProcessError::CommandNotAbsolute(rule.path.to_owned()));
+            Ok(process_information)
+        }
     }
+}
 
+/// Attaches to the console of the process that started fassoc-proxy, e.g. the
+/// terminal it was run from, so that what is printed is shown there. Release
+/// builds use the windows subsystem, and so have no console of their own.
+pub fn attach_parent_console() {
+    // This fails if there already is a console, as in debug builds, or if the
+    // parent has none, e.g. when started from Explorer, which is harmless.
     unsafe {
-        let mut process_information = PROCESS_INFORMATION::default();
-
-        let result = CreateProcessA(
-            params.command,
-            params.arguments,
-            &params.process_attributes,
-            &params.thread_attributes,
-            params.inherit_handles,
-            params.creation_flags,
-            params.environment,
-            params.cwd,
-            &params.startup_info,
-            &mut process_information,
-        );
-
-        if result.0 == 0 {
-            log::error!("WinAPI reported that the process creation failed (result == 0)");
-        }
-
-        log::debug!("CreateProcessA returned: {:?}", result);
-        log::debug!("Process Information ---------\n{:?}", process_information);
-
-        Ok(process_information)
+        AttachConsole(ATTACH_PARENT_PROCESS);
     }
 }